@@ -5,15 +5,57 @@ pub enum AstNode {
     Statement(Statement),
 }
 
+/// Location of a node in the source: the byte range `start..end` and the
+/// 1-based line and column of its first character
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Span {
+    /// Index of the source file; the file passed to `quentin build` is 0
+    pub file_id: u32,
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Span {
+    /// Span from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+/// A value, such as an error, paired with the source it refers to
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum StatementKind {
     SystemInclude, // Placeholder
     SystemInit(VariableDeclaration),
     SystemSet(VariableAssignment),
     SystemLog(Log),
     FunctionDeclaration(FunctionDeclaration),
     SystemExec(FunctionCall),
+    SystemInput(Input),
+    SystemExit(Exit),
+    Try(TryCatch),
     Return(Expression),
+    Expression(Expression),
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -32,9 +74,30 @@ pub struct VariableAssignment {
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Log {
     pub log_type: String,
+    /// Variable references from `arguments { ... }`, attached to the message as fields
+    pub arguments: Vec<Expression>,
     pub message: Expression,
 }
 
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Input {
+    pub name: String,
+    pub prompt: Option<Expression>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Exit {
+    pub code: Option<Expression>,
+}
+
+/// `try { body } catch (error_name) { handler }`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TryCatch {
+    pub body: Vec<Statement>,
+    pub error_name: String,
+    pub handler: Vec<Statement>,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FunctionDeclaration {
     pub name: String,
@@ -46,26 +109,109 @@ pub struct FunctionDeclaration {
 pub struct FunctionCall {
     pub name: String,
     pub args: Vec<(String, Expression)>,
+    /// `"type": task` runs the function as a task instead of calling it
+    pub spawn: bool,
+    /// Variable that receives the id of the spawned task
+    pub handle: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ExpressionKind {
     Value(Value),
     Variable(String),
     Concat(Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+    Coalesce(Box<Expression>, Box<Expression>),
+    Arithmetic(ArithmeticOp, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl std::fmt::Display for ArithmeticOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            ArithmeticOp::Add => "+",
+            ArithmeticOp::Subtract => "-",
+            ArithmeticOp::Multiply => "*",
+            ArithmeticOp::Divide => "/",
+            ArithmeticOp::Remainder => "%",
+        };
+        write!(f, "{}", symbol)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum DataType {
     String,
     Number,
+    Int,
     Bool,
+    /// Any value, including maps; used for decoded JSON
+    Any,
+    Array(Box<DataType>),
+    Nullable(Box<DataType>),
+}
+
+impl DataType {
+    pub fn is_nullable(&self) -> bool {
+        matches!(self, DataType::Nullable(_))
+    }
+
+    /// The type without its nullable marker
+    pub fn base(&self) -> &DataType {
+        match self {
+            DataType::Nullable(inner) => inner.base(),
+            other => other,
+        }
+    }
+
+    pub fn array(self) -> DataType {
+        DataType::Array(Box::new(self))
+    }
+
+    pub fn nullable(self) -> DataType {
+        match self {
+            DataType::Nullable(_) => self,
+            other => DataType::Nullable(Box::new(other)),
+        }
+    }
+}
+
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::String => write!(f, "string"),
+            DataType::Number => write!(f, "number"),
+            DataType::Int => write!(f, "int"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Any => write!(f, "any"),
+            DataType::Array(element) => write!(f, "{}[]", element),
+            DataType::Nullable(inner) => write!(f, "{}?", inner),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Value {
     String(String),
     Number(f64),
+    Int(i64),
     Bool(bool),
+    Array(Vec<Value>),
+    /// Key/value pairs in insertion order
+    Map(Vec<(String, Value)>),
     Null,
 }
\ No newline at end of file
@@ -1,9 +1,8 @@
 use std::fs;
 use std::path::{Path, PathBuf};
-use serde_json;
 
 pub const BUILD_DIR: &str = "build";
-pub const BYTECODE_VERSION: u32 = 1;
+pub const BYTECODE_VERSION: u32 = 17;
 pub const COMPILER_VERSION: &str = "0.1.0";
 
 /// Represents the build output structure for a compiled package
@@ -64,14 +63,61 @@ impl PackageBuilder {
         Ok(())
     }
 
+    /// Path of the file a stage is written to with `--emit`
+    pub fn emit_path(&self, stage: EmitStage) -> PathBuf {
+        self.package_dir.join(stage.file_name())
+    }
+
     /// Get the package directory path
     pub fn package_dir(&self) -> &Path {
         &self.package_dir
     }
+}
+
+/// A compiler stage that `quentin build --emit` can write out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitStage {
+    /// Every token other than whitespace, with its position
+    Tokens,
+    /// The syntax tree, as JSON
+    Ast,
+    /// The intermediate representation, as JSON
+    Ir,
+    /// A listing of the instructions in `program.qbin`
+    Bytecode,
+}
+
+impl EmitStage {
+    pub const ALL: &'static [EmitStage] = &[EmitStage::Tokens, EmitStage::Ast, EmitStage::Ir, EmitStage::Bytecode];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EmitStage::Tokens => "tokens",
+            EmitStage::Ast => "ast",
+            EmitStage::Ir => "ir",
+            EmitStage::Bytecode => "bytecode",
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            EmitStage::Tokens => "tokens.txt",
+            EmitStage::Ast => "ast.json",
+            EmitStage::Ir => "ir.json",
+            EmitStage::Bytecode => "bytecode.txt",
+        }
+    }
+}
+
+impl std::str::FromStr for EmitStage {
+    type Err = String;
 
-    /// Get the source file name
-    pub fn source_file(&self) -> &str {
-        &self.source_file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        EmitStage::ALL.iter().copied().find(|stage| stage.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = EmitStage::ALL.iter().map(|stage| stage.name()).collect();
+            format!("unknown stage '{}', expected one of {}", s, names.join(", "))
+        })
     }
 }
 
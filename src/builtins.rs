@@ -0,0 +1,415 @@
+use serde::{Serialize, Deserialize};
+use crate::ast::*;
+use crate::vm::{RuntimeError, value_to_string};
+
+pub mod fs;
+pub mod json;
+pub mod math;
+pub mod strings;
+pub mod time;
+
+/// Functions provided by the runtime that can be called from expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Builtin {
+    ToNumber,
+    ToInt,
+    ToString,
+    ToBool,
+    ParseNumber,
+    Input,
+    Length,
+    Upper,
+    Lower,
+    Trim,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Find,
+    Substring,
+    Replace,
+    Split,
+    Join,
+    Repeat,
+    Abs,
+    Min,
+    Max,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+    Pow,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Log,
+    Exp,
+    Pi,
+    E,
+    Random,
+    RandomInt,
+    SeedRandom,
+    ReadFile,
+    WriteFile,
+    AppendLine,
+    FileExists,
+    ListDir,
+    DeleteFile,
+    Args,
+    Arg,
+    ArgCount,
+    Env,
+    Now,
+    Clock,
+    Sleep,
+    FormatTime,
+    JsonParse,
+    JsonEncode,
+    JsonPretty,
+    JsonGet,
+    JsonSet,
+    JsonKeys,
+    Yield,
+    Channel,
+    Send,
+    Receive,
+    Close,
+    Wait,
+    WaitAll,
+}
+
+/// Type accepted by a builtin parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamType {
+    Any,
+    Exact(DataType),
+}
+
+/// Typed signature of a builtin, used by the semantic pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<ParamType>,
+    /// `None` for builtins that are only called for their effect and return null
+    pub return_type: Option<DataType>,
+}
+
+impl Builtin {
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::ToNumber,
+        Builtin::ToInt,
+        Builtin::ToString,
+        Builtin::ToBool,
+        Builtin::ParseNumber,
+        Builtin::Input,
+        Builtin::Length,
+        Builtin::Upper,
+        Builtin::Lower,
+        Builtin::Trim,
+        Builtin::Contains,
+        Builtin::StartsWith,
+        Builtin::EndsWith,
+        Builtin::Find,
+        Builtin::Substring,
+        Builtin::Replace,
+        Builtin::Split,
+        Builtin::Join,
+        Builtin::Repeat,
+        Builtin::Abs,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Round,
+        Builtin::Sqrt,
+        Builtin::Pow,
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Asin,
+        Builtin::Acos,
+        Builtin::Atan,
+        Builtin::Log,
+        Builtin::Exp,
+        Builtin::Pi,
+        Builtin::E,
+        Builtin::Random,
+        Builtin::RandomInt,
+        Builtin::SeedRandom,
+        Builtin::ReadFile,
+        Builtin::WriteFile,
+        Builtin::AppendLine,
+        Builtin::FileExists,
+        Builtin::ListDir,
+        Builtin::DeleteFile,
+        Builtin::Args,
+        Builtin::Arg,
+        Builtin::ArgCount,
+        Builtin::Env,
+        Builtin::Now,
+        Builtin::Clock,
+        Builtin::Sleep,
+        Builtin::FormatTime,
+        Builtin::JsonParse,
+        Builtin::JsonEncode,
+        Builtin::JsonPretty,
+        Builtin::JsonGet,
+        Builtin::JsonSet,
+        Builtin::JsonKeys,
+        Builtin::Yield,
+        Builtin::Channel,
+        Builtin::Send,
+        Builtin::Receive,
+        Builtin::Close,
+        Builtin::Wait,
+        Builtin::WaitAll,
+    ];
+
+    /// Look up a builtin by the name used in Q source
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Self::ALL.iter().copied().find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::ToNumber => "to_number",
+            Builtin::ToInt => "to_int",
+            Builtin::ToString => "to_string",
+            Builtin::ToBool => "to_bool",
+            Builtin::ParseNumber => "parse_number",
+            Builtin::Input => "input",
+            Builtin::Length => "length",
+            Builtin::Upper => "upper",
+            Builtin::Lower => "lower",
+            Builtin::Trim => "trim",
+            Builtin::Contains => "contains",
+            Builtin::StartsWith => "starts_with",
+            Builtin::EndsWith => "ends_with",
+            Builtin::Find => "find",
+            Builtin::Substring => "substring",
+            Builtin::Replace => "replace",
+            Builtin::Split => "split",
+            Builtin::Join => "join",
+            Builtin::Repeat => "repeat",
+            Builtin::Abs => "abs",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Round => "round",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Pow => "pow",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Asin => "asin",
+            Builtin::Acos => "acos",
+            Builtin::Atan => "atan",
+            Builtin::Log => "log",
+            Builtin::Exp => "exp",
+            Builtin::Pi => "pi",
+            Builtin::E => "e",
+            Builtin::Random => "random",
+            Builtin::RandomInt => "random_int",
+            Builtin::SeedRandom => "seed_random",
+            Builtin::ReadFile => "read_file",
+            Builtin::WriteFile => "write_file",
+            Builtin::AppendLine => "append_line",
+            Builtin::FileExists => "file_exists",
+            Builtin::ListDir => "list_dir",
+            Builtin::DeleteFile => "delete_file",
+            Builtin::Args => "args",
+            Builtin::Arg => "arg",
+            Builtin::ArgCount => "arg_count",
+            Builtin::Env => "env",
+            Builtin::Now => "now",
+            Builtin::Clock => "clock",
+            Builtin::Sleep => "sleep",
+            Builtin::FormatTime => "format_time",
+            Builtin::JsonParse => "json_parse",
+            Builtin::JsonEncode => "json_encode",
+            Builtin::JsonPretty => "json_pretty",
+            Builtin::JsonGet => "json_get",
+            Builtin::JsonSet => "json_set",
+            Builtin::JsonKeys => "json_keys",
+            Builtin::Yield => "yield",
+            Builtin::Channel => "channel",
+            Builtin::Send => "send",
+            Builtin::Receive => "receive",
+            Builtin::Close => "close",
+            Builtin::Wait => "wait",
+            Builtin::WaitAll => "wait_all",
+        }
+    }
+
+    pub fn signature(self) -> Signature {
+        use ParamType::*;
+        let string = || Exact(DataType::String);
+        let number = || Exact(DataType::Number);
+        let int = || Exact(DataType::Int);
+        let (params, return_type) = match self {
+            Builtin::ToNumber => (vec![Any], Some(DataType::Number)),
+            Builtin::ToInt => (vec![Any], Some(DataType::Int)),
+            Builtin::ToString => (vec![Any], Some(DataType::String)),
+            Builtin::ToBool => (vec![Any], Some(DataType::Bool)),
+            // Returns null when the string is not a number
+            Builtin::ParseNumber => (vec![string()], Some(DataType::Number.nullable())),
+            // Returns null at end of input
+            Builtin::Input => (vec![string()], Some(DataType::String.nullable())),
+            Builtin::Length => (vec![string()], Some(DataType::Int)),
+            Builtin::Upper | Builtin::Lower | Builtin::Trim => (vec![string()], Some(DataType::String)),
+            Builtin::Contains | Builtin::StartsWith | Builtin::EndsWith => (vec![string(), string()], Some(DataType::Bool)),
+            // Returns null when the needle does not occur
+            Builtin::Find => (vec![string(), string()], Some(DataType::Int.nullable())),
+            Builtin::Substring => (vec![string(), int(), int()], Some(DataType::String)),
+            Builtin::Replace => (vec![string(), string(), string()], Some(DataType::String)),
+            Builtin::Split => (vec![string(), string()], Some(DataType::String.array())),
+            Builtin::Join => (vec![Exact(DataType::String.array()), string()], Some(DataType::String)),
+            Builtin::Repeat => (vec![string(), int()], Some(DataType::String)),
+            Builtin::Abs | Builtin::Floor | Builtin::Ceil | Builtin::Round | Builtin::Sqrt
+            | Builtin::Sin | Builtin::Cos | Builtin::Tan | Builtin::Asin | Builtin::Acos | Builtin::Atan
+            | Builtin::Log | Builtin::Exp => (vec![number()], Some(DataType::Number)),
+            Builtin::Min | Builtin::Max | Builtin::Pow => (vec![number(), number()], Some(DataType::Number)),
+            Builtin::Pi | Builtin::E | Builtin::Random => (vec![], Some(DataType::Number)),
+            Builtin::RandomInt => (vec![int(), int()], Some(DataType::Int)),
+            Builtin::SeedRandom => (vec![int()], None),
+            Builtin::ReadFile => (vec![string()], Some(DataType::String)),
+            Builtin::WriteFile | Builtin::AppendLine => (vec![string(), string()], None),
+            Builtin::FileExists => (vec![string()], Some(DataType::Bool)),
+            Builtin::ListDir => (vec![string()], Some(DataType::String.array())),
+            Builtin::DeleteFile => (vec![string()], None),
+            Builtin::Args => (vec![], Some(DataType::String.array())),
+            // Returns null when there is no argument at the index
+            Builtin::Arg => (vec![int()], Some(DataType::String.nullable())),
+            Builtin::ArgCount => (vec![], Some(DataType::Int)),
+            // Returns null when the variable is not set
+            Builtin::Env => (vec![string()], Some(DataType::String.nullable())),
+            Builtin::Now | Builtin::Clock => (vec![], Some(DataType::Number)),
+            Builtin::Sleep => (vec![int()], None),
+            Builtin::FormatTime => (vec![number()], Some(DataType::String)),
+            Builtin::JsonParse => (vec![string()], Some(DataType::Any)),
+            Builtin::JsonEncode | Builtin::JsonPretty => (vec![Any], Some(DataType::String)),
+            // Returns null when the key or index is missing; null containers pass through so lookups chain
+            Builtin::JsonGet => (vec![Exact(DataType::Any.nullable()), Any], Some(DataType::Any.nullable())),
+            Builtin::JsonSet => (vec![Exact(DataType::Any), Any, Any], Some(DataType::Any)),
+            Builtin::JsonKeys => (vec![Exact(DataType::Any)], Some(DataType::String.array())),
+            Builtin::Yield | Builtin::WaitAll => (vec![], None),
+            Builtin::Channel => (vec![], Some(DataType::Int)),
+            Builtin::Send => (vec![int(), Any], None),
+            // Blocks until a value arrives; null once the channel is closed and empty
+            Builtin::Receive => (vec![int()], Some(DataType::Any.nullable())),
+            Builtin::Close | Builtin::Wait => (vec![int()], None),
+        };
+        Signature { params, return_type }
+    }
+}
+
+pub(crate) fn expect_string(val: &Value) -> Result<&str, RuntimeError> {
+    match val {
+        Value::String(s) => Ok(s),
+        other => Err(RuntimeError::TypeError(format!("expected string, found {}", value_to_string(other)))),
+    }
+}
+
+/// Accept either numeric type as a float
+pub(crate) fn expect_number(val: &Value) -> Result<f64, RuntimeError> {
+    match val {
+        Value::Number(n) => Ok(*n),
+        Value::Int(n) => Ok(*n as f64),
+        other => Err(RuntimeError::TypeError(format!("expected number, found {}", value_to_string(other)))),
+    }
+}
+
+pub(crate) fn expect_int(val: &Value) -> Result<i64, RuntimeError> {
+    match val {
+        Value::Int(n) => Ok(*n),
+        other => Err(RuntimeError::TypeError(format!("expected int, found {}", value_to_string(other)))),
+    }
+}
+
+pub(crate) fn expect_array(val: &Value) -> Result<&[Value], RuntimeError> {
+    match val {
+        Value::Array(items) => Ok(items),
+        other => Err(RuntimeError::TypeError(format!("expected array, found {}", value_to_string(other)))),
+    }
+}
+
+/// Convert a value to a number, failing for values without a numeric meaning
+pub fn to_number(val: &Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::Number(n) => Ok(Value::Number(*n)),
+        Value::Int(n) => Ok(Value::Number(*n as f64)),
+        Value::Bool(b) => Ok(Value::Number(if *b { 1.0 } else { 0.0 })),
+        Value::String(s) => parse_f64(s)
+            .map(Value::Number)
+            .ok_or_else(|| RuntimeError::ConversionFailed(format!("cannot convert \"{}\" to number", s))),
+        Value::Array(_) => Err(RuntimeError::ConversionFailed("cannot convert an array to number".to_string())),
+        Value::Map(_) => Err(RuntimeError::ConversionFailed("cannot convert a map to number".to_string())),
+        Value::Null => Err(RuntimeError::ConversionFailed("cannot convert null to number".to_string())),
+    }
+}
+
+/// Convert a value to an int; numbers are truncated toward zero
+pub fn to_int(val: &Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::Int(n) => Ok(Value::Int(*n)),
+        Value::Number(n) => f64_to_i64(*n)
+            .map(Value::Int)
+            .ok_or_else(|| RuntimeError::ConversionFailed(format!("{} does not fit in an int", n))),
+        Value::Bool(b) => Ok(Value::Int(*b as i64)),
+        Value::String(s) => {
+            let trimmed = s.trim();
+            trimmed.parse::<i64>().ok()
+                .or_else(|| parse_f64(trimmed).and_then(f64_to_i64))
+                .map(Value::Int)
+                .ok_or_else(|| RuntimeError::ConversionFailed(format!("cannot convert \"{}\" to int", s)))
+        }
+        Value::Array(_) => Err(RuntimeError::ConversionFailed("cannot convert an array to int".to_string())),
+        Value::Map(_) => Err(RuntimeError::ConversionFailed("cannot convert a map to int".to_string())),
+        Value::Null => Err(RuntimeError::ConversionFailed("cannot convert null to int".to_string())),
+    }
+}
+
+pub fn to_string(val: &Value) -> Result<Value, RuntimeError> {
+    Ok(Value::String(value_to_string(val)))
+}
+
+/// Convert a value to a bool; only "true" and "false" are accepted for strings
+pub fn to_bool(val: &Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::Bool(b) => Ok(Value::Bool(*b)),
+        Value::Number(n) => Ok(Value::Bool(*n != 0.0)),
+        Value::Int(n) => Ok(Value::Bool(*n != 0)),
+        Value::String(s) => match s.trim() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(RuntimeError::ConversionFailed(format!("cannot convert \"{}\" to bool", s))),
+        },
+        Value::Array(_) => Err(RuntimeError::ConversionFailed("cannot convert an array to bool".to_string())),
+        Value::Map(_) => Err(RuntimeError::ConversionFailed("cannot convert a map to bool".to_string())),
+        Value::Null => Err(RuntimeError::ConversionFailed("cannot convert null to bool".to_string())),
+    }
+}
+
+/// Parse a string into a number, returning null instead of failing
+pub fn parse_number(val: &Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::String(s) => Ok(parse_f64(s).map(Value::Number).unwrap_or(Value::Null)),
+        _ => Ok(Value::Null),
+    }
+}
+
+fn parse_f64(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+fn f64_to_i64(n: f64) -> Option<i64> {
+    let truncated = n.trunc();
+    // i64::MAX is not exactly representable, so compare against 2^63
+    if truncated.is_finite() && truncated >= i64::MIN as f64 && truncated < 9_223_372_036_854_775_808.0 {
+        Some(truncated as i64)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use crate::ast::*;
+use crate::vm::RuntimeError;
+use super::expect_string;
+
+/// Paths scripts may access. Everything is denied unless granted on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+}
+
+impl Permissions {
+    pub fn allow_read(&mut self, path: &Path) {
+        self.read.push(resolve(path));
+    }
+
+    pub fn allow_write(&mut self, path: &Path) {
+        self.write.push(resolve(path));
+    }
+
+    /// Resolve `path` and check it lies under one of the granted roots
+    fn check(&self, path: &str, access: Access) -> Result<PathBuf, RuntimeError> {
+        let resolved = resolve(Path::new(path));
+        let (roots, kind) = match access {
+            Access::Read => (&self.read, "read"),
+            Access::Write => (&self.write, "write"),
+        };
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(RuntimeError::PermissionDenied(format!(
+                "{} access to \"{}\" requires --allow-{}",
+                kind, path, kind
+            )))
+        }
+    }
+}
+
+/// Make a path absolute and normalise it, following symlinks for the part that exists
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::env::current_dir().unwrap_or_default().join(path);
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    // Canonicalise the longest existing ancestor so symlinks cannot escape a root
+    let mut existing = normalized.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return normalized,
+        }
+    }
+    let mut resolved = existing.canonicalize().unwrap_or_else(|_| existing.to_path_buf());
+    resolved.extend(rest.iter().rev());
+    resolved
+}
+
+fn io_error(path: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::Io(format!("{}: {}", path, err))
+}
+
+pub fn read_file(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = expect_string(&args[0])?;
+    let resolved = permissions.check(path, Access::Read)?;
+    fs::read_to_string(resolved).map(Value::String).map_err(|e| io_error(path, e))
+}
+
+pub fn write_file(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (path, content) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    let resolved = permissions.check(path, Access::Write)?;
+    fs::write(resolved, content).map_err(|e| io_error(path, e))?;
+    Ok(Value::Null)
+}
+
+pub fn append_line(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (path, line) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    let resolved = permissions.check(path, Access::Write)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(resolved)
+        .map_err(|e| io_error(path, e))?;
+    writeln!(file, "{}", line).map_err(|e| io_error(path, e))?;
+    Ok(Value::Null)
+}
+
+pub fn file_exists(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = expect_string(&args[0])?;
+    let resolved = permissions.check(path, Access::Read)?;
+    Ok(Value::Bool(resolved.exists()))
+}
+
+/// Entry names of a directory, sorted so output is deterministic
+pub fn list_dir(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = expect_string(&args[0])?;
+    let resolved = permissions.check(path, Access::Read)?;
+    let mut names = fs::read_dir(resolved)
+        .map_err(|e| io_error(path, e))?
+        .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| io_error(path, e))?;
+    names.sort();
+    Ok(Value::Array(names.into_iter().map(Value::String).collect()))
+}
+
+pub fn delete_file(permissions: &Permissions, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = expect_string(&args[0])?;
+    let resolved = permissions.check(path, Access::Write)?;
+    fs::remove_file(resolved).map_err(|e| io_error(path, e))?;
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &Path) -> Value {
+        Value::String(path.to_str().unwrap().to_string())
+    }
+
+    fn denied(result: Result<Value, RuntimeError>) -> bool {
+        matches!(result, Err(RuntimeError::PermissionDenied(_)))
+    }
+
+    #[test]
+    fn access_outside_granted_directories_is_denied() {
+        let dir = std::env::temp_dir().join(format!("quentin-fs-{}", std::process::id()));
+        let (allowed, secret) = (dir.join("allowed"), dir.join("secret"));
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(&secret).unwrap();
+        fs::write(secret.join("data.txt"), "secret").unwrap();
+        let escape = allowed.join("..").join("secret");
+
+        let mut permissions = Permissions::default();
+        assert!(denied(read_file(&permissions, &[path(&secret.join("data.txt"))])));
+        permissions.allow_read(&allowed);
+
+        let note = allowed.join("note.txt");
+        assert!(denied(write_file(&permissions, &[path(&note), Value::String("x".into())])));
+        permissions.allow_write(&allowed);
+        write_file(&permissions, &[path(&note), Value::String("x".into())]).unwrap();
+        assert_eq!(read_file(&permissions, &[path(&note)]).unwrap(), Value::String("x".into()));
+
+        assert!(denied(read_file(&permissions, &[path(&secret.join("data.txt"))])));
+        assert!(denied(read_file(&permissions, &[path(&escape.join("data.txt"))])));
+        assert!(denied(file_exists(&permissions, &[path(&escape.join("data.txt"))])));
+        assert!(denied(list_dir(&permissions, &[path(&escape)])));
+        assert!(denied(write_file(&permissions, &[path(&escape.join("new.txt")), Value::String("x".into())])));
+        assert!(denied(append_line(&permissions, &[path(&escape.join("data.txt")), Value::String("x".into())])));
+        assert!(denied(delete_file(&permissions, &[path(&escape.join("data.txt"))])));
+        assert!(!secret.join("new.txt").exists());
+        assert_eq!(fs::read_to_string(secret.join("data.txt")).unwrap(), "secret");
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, allowed.join("link")).unwrap();
+            assert!(denied(read_file(&permissions, &[path(&allowed.join("link").join("data.txt"))])));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
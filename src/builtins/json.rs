@@ -0,0 +1,190 @@
+use serde_json::{Map, Number};
+use crate::ast::*;
+use crate::vm::{RuntimeError, value_to_string};
+use super::expect_string;
+
+/// Parse JSON text. Objects become maps (keeping key order) and all numbers become `number`.
+pub fn parse(args: &[Value]) -> Result<Value, RuntimeError> {
+    let text = expect_string(&args[0])?;
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| {
+        // serde_json appends the position to its message; it is reported separately
+        let message = e.to_string();
+        let suffix = format!(" at line {} column {}", e.line(), e.column());
+        RuntimeError::JsonParse {
+            message: message.strip_suffix(&suffix).unwrap_or(&message).to_string(),
+            line: e.line(),
+            column: e.column(),
+        }
+    })?;
+    Ok(from_json(json))
+}
+
+pub fn encode(args: &[Value]) -> Result<Value, RuntimeError> {
+    let json = to_json(&args[0])?;
+    Ok(Value::String(json.to_string()))
+}
+
+pub fn encode_pretty(args: &[Value]) -> Result<Value, RuntimeError> {
+    let json = to_json(&args[0])?;
+    let text = serde_json::to_string_pretty(&json).map_err(|e| RuntimeError::InvalidArgument(e.to_string()))?;
+    Ok(Value::String(text))
+}
+
+/// Look up a key in a map or an index in an array; null when absent
+pub fn get(args: &[Value]) -> Result<Value, RuntimeError> {
+    let found = match (&args[0], &args[1]) {
+        (Value::Null, _) => None,
+        (Value::Map(entries), Value::String(key)) => {
+            entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+        }
+        (Value::Array(items), Value::Int(index)) => {
+            usize::try_from(*index).ok().and_then(|index| items.get(index)).cloned()
+        }
+        (container, key) => return Err(invalid_access(container, key)),
+    };
+    Ok(found.unwrap_or(Value::Null))
+}
+
+/// Return a copy with the key or index set; array indexes may append at the end
+pub fn set(args: &[Value]) -> Result<Value, RuntimeError> {
+    let new_value = args[2].clone();
+    match (&args[0], &args[1]) {
+        (Value::Map(entries), Value::String(key)) => {
+            let mut entries = entries.clone();
+            match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, value)) => *value = new_value,
+                None => entries.push((key.clone(), new_value)),
+            }
+            Ok(Value::Map(entries))
+        }
+        (Value::Array(items), Value::Int(index)) => {
+            let mut items = items.clone();
+            match usize::try_from(*index) {
+                Ok(index) if index < items.len() => items[index] = new_value,
+                Ok(index) if index == items.len() => items.push(new_value),
+                _ => {
+                    return Err(RuntimeError::InvalidArgument(format!(
+                        "index {} is out of bounds for an array of length {}",
+                        index,
+                        items.len()
+                    )))
+                }
+            }
+            Ok(Value::Array(items))
+        }
+        (container, key) => Err(invalid_access(container, key)),
+    }
+}
+
+pub fn keys(args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Map(entries) => Ok(Value::Array(entries.iter().map(|(k, _)| Value::String(k.clone())).collect())),
+        other => Err(RuntimeError::TypeError(format!("expected map, found {}", value_to_string(other)))),
+    }
+}
+
+fn invalid_access(container: &Value, key: &Value) -> RuntimeError {
+    RuntimeError::TypeError(format!(
+        "cannot index {} with {}; maps take string keys and arrays take int indexes",
+        value_to_string(container),
+        value_to_string(key)
+    ))
+}
+
+fn from_json(json: serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        serde_json::Value::String(s) => Value::String(s),
+        serde_json::Value::Array(items) => Value::Array(items.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(entries) => {
+            Value::Map(entries.into_iter().map(|(k, v)| (k, from_json(v))).collect())
+        }
+    }
+}
+
+pub(crate) fn to_json(val: &Value) -> Result<serde_json::Value, RuntimeError> {
+    Ok(match val {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(n) => serde_json::Value::Number((*n).into()),
+        // Whole numbers are written without a fractional part
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => {
+            serde_json::Value::Number((*n as i64).into())
+        }
+        Value::Number(n) => serde_json::Value::Number(Number::from_f64(*n).ok_or_else(|| {
+            RuntimeError::InvalidArgument(format!("{} cannot be represented in JSON", n))
+        })?),
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Array(items) => serde_json::Value::Array(items.iter().map(to_json).collect::<Result<_, _>>()?),
+        Value::Map(entries) => {
+            let mut map = Map::new();
+            for (k, v) in entries {
+                map.insert(k.clone(), to_json(v)?);
+            }
+            serde_json::Value::Object(map)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn parse_errors_report_line_and_column() {
+        match parse(&[string("{\n  \"a\": 1,\n  \"b\" 2\n}")]) {
+            Err(RuntimeError::JsonParse { message, line, column }) => {
+                assert_eq!((message.as_str(), line, column), ("expected `:`", 3, 7));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn escapes_and_unicode_round_trip() {
+        let text = r#"{"quote":"say \"hi\"","path":"C:\\tmp","lines":"a\nb\tc","accent":"héllo","escaped":"\u00e9\ud83e\udd80","emoji":"🦀"}"#;
+        let value = parse(&[string(text)]).unwrap();
+        let Value::Map(entries) = &value else { panic!("{:?}", value) };
+        let strings: Vec<&Value> = entries.iter().map(|(_, v)| v).collect();
+        assert_eq!(strings, [&string("say \"hi\""), &string("C:\\tmp"), &string("a\nb\tc"), &string("héllo"), &string("é🦀"), &string("🦀")]);
+
+        let encoded = encode(std::slice::from_ref(&value)).unwrap();
+        assert_eq!(encoded, string(r#"{"quote":"say \"hi\"","path":"C:\\tmp","lines":"a\nb\tc","accent":"héllo","escaped":"é🦀","emoji":"🦀"}"#));
+        assert_eq!(parse(&[encoded]).unwrap(), value);
+    }
+
+    #[test]
+    fn pretty_output_keeps_key_order() {
+        let value = parse(&[string(r#"{"z":[1,2.5],"a":{"n":null,"t":true}}"#)]).unwrap();
+        assert_eq!(
+            encode_pretty(&[value]).unwrap(),
+            string("{\n  \"z\": [\n    1,\n    2.5\n  ],\n  \"a\": {\n    \"n\": null,\n    \"t\": true\n  }\n}")
+        );
+    }
+
+    #[test]
+    fn get_and_set_on_missing_keys() {
+        let map = parse(&[string(r#"{"a":1}"#)]).unwrap();
+        assert_eq!(get(&[map.clone(), string("missing")]).unwrap(), Value::Null);
+        assert_eq!(get(&[Value::Null, string("a")]).unwrap(), Value::Null);
+        assert_eq!(get(&[Value::Array(vec![Value::Int(1)]), Value::Int(1)]).unwrap(), Value::Null);
+        assert_eq!(get(&[Value::Array(vec![Value::Int(1)]), Value::Int(-1)]).unwrap(), Value::Null);
+
+        let updated = set(&[map.clone(), string("b"), Value::Int(2)]).unwrap();
+        assert_eq!(updated, Value::Map(vec![("a".into(), Value::Number(1.0)), ("b".into(), Value::Int(2))]));
+        assert_eq!(set(&[updated, string("a"), Value::Null]).unwrap(), Value::Map(vec![("a".into(), Value::Null), ("b".into(), Value::Int(2))]));
+        assert_eq!(map, Value::Map(vec![("a".into(), Value::Number(1.0))]));
+
+        let items = Value::Array(vec![Value::Int(1)]);
+        assert_eq!(set(&[items.clone(), Value::Int(1), Value::Int(2)]).unwrap(), Value::Array(vec![Value::Int(1), Value::Int(2)]));
+        assert!(matches!(set(&[items.clone(), Value::Int(2), Value::Int(3)]), Err(RuntimeError::InvalidArgument(_))));
+        assert!(matches!(set(&[items, string("a"), Value::Int(3)]), Err(RuntimeError::TypeError(_))));
+        assert!(matches!(set(&[Value::Null, string("a"), Value::Int(3)]), Err(RuntimeError::TypeError(_))));
+    }
+}
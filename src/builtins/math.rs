@@ -0,0 +1,67 @@
+use crate::ast::*;
+use crate::vm::{RuntimeError, value_to_string};
+use super::expect_number;
+
+/// Apply a unary float function, rejecting results outside its domain
+pub fn unary(name: &str, args: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    let x = expect_number(&args[0])?;
+    checked(name, args, f(x))
+}
+
+/// Apply a binary float function, rejecting results outside its domain
+pub fn binary(name: &str, args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+    let (x, y) = (expect_number(&args[0])?, expect_number(&args[1])?);
+    checked(name, args, f(x, y))
+}
+
+fn checked(name: &str, args: &[Value], result: f64) -> Result<Value, RuntimeError> {
+    if result.is_nan() {
+        let args: Vec<String> = args.iter().map(value_to_string).collect();
+        return Err(RuntimeError::InvalidArgument(format!("{}({}) is undefined", name, args.join(", "))));
+    }
+    Ok(Value::Number(result))
+}
+
+/// Small deterministic pseudo-random generator (SplitMix64).
+/// Runs with the same seed produce the same sequence.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seed from the clock when no seed was given
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform number in [0, 1)
+    pub fn random(&mut self) -> Value {
+        Value::Number((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    /// Uniform int in [min, max], both inclusive
+    pub fn random_int(&mut self, min: i64, max: i64) -> Result<Value, RuntimeError> {
+        if min > max {
+            return Err(RuntimeError::InvalidArgument(format!("random_int range {}..{} is empty", min, max)));
+        }
+        let span = (max as i128 - min as i128 + 1) as u128;
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        Ok(Value::Int((min as i128 + offset as i128) as i64))
+    }
+}
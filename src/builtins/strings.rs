@@ -0,0 +1,135 @@
+use crate::ast::*;
+use crate::vm::RuntimeError;
+use super::{expect_array, expect_int, expect_string};
+
+// Positions and lengths count Unicode scalar values (chars), not bytes.
+
+pub fn length(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = expect_string(&args[0])?;
+    Ok(Value::Int(s.chars().count() as i64))
+}
+
+pub fn upper(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(expect_string(&args[0])?.to_uppercase()))
+}
+
+pub fn lower(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(expect_string(&args[0])?.to_lowercase()))
+}
+
+pub fn trim(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(expect_string(&args[0])?.trim().to_string()))
+}
+
+pub fn contains(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, needle) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    Ok(Value::Bool(s.contains(needle)))
+}
+
+pub fn starts_with(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, prefix) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    Ok(Value::Bool(s.starts_with(prefix)))
+}
+
+pub fn ends_with(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, suffix) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    Ok(Value::Bool(s.ends_with(suffix)))
+}
+
+/// Index of the first occurrence, or null when absent
+pub fn find(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, needle) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    Ok(match s.find(needle) {
+        Some(byte_index) => Value::Int(s[..byte_index].chars().count() as i64),
+        None => Value::Null,
+    })
+}
+
+/// Characters from `start` up to, but not including, `end`
+pub fn substring(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = expect_string(&args[0])?;
+    let (start, end) = (expect_int(&args[1])?, expect_int(&args[2])?);
+    let char_count = s.chars().count() as i64;
+    if start < 0 || end < start || end > char_count {
+        return Err(RuntimeError::InvalidArgument(format!(
+            "substring range {}..{} is out of bounds for a string of length {}",
+            start, end, char_count
+        )));
+    }
+    let result = s.chars().skip(start as usize).take((end - start) as usize).collect();
+    Ok(Value::String(result))
+}
+
+/// Replace every occurrence of `from` with `to`
+pub fn replace(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = expect_string(&args[0])?;
+    let (from, to) = (expect_string(&args[1])?, expect_string(&args[2])?);
+    if from.is_empty() {
+        return Err(RuntimeError::InvalidArgument("replace pattern must not be empty".to_string()));
+    }
+    Ok(Value::String(s.replace(from, to)))
+}
+
+pub fn split(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, separator) = (expect_string(&args[0])?, expect_string(&args[1])?);
+    if separator.is_empty() {
+        return Err(RuntimeError::InvalidArgument("split separator must not be empty".to_string()));
+    }
+    let parts = s.split(separator).map(|part| Value::String(part.to_string())).collect();
+    Ok(Value::Array(parts))
+}
+
+pub fn join(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (items, separator) = (expect_array(&args[0])?, expect_string(&args[1])?);
+    let parts = items.iter().map(expect_string).collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::String(parts.join(separator)))
+}
+
+/// Longest string `repeat` builds, in bytes
+const MAX_REPEAT_LEN: usize = 64 * 1024 * 1024;
+
+pub fn repeat(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, count) = (expect_string(&args[0])?, expect_int(&args[1])?);
+    if count < 0 {
+        return Err(RuntimeError::InvalidArgument(format!("repeat count must not be negative, got {}", count)));
+    }
+    let len = usize::try_from(count).ok().and_then(|count| s.len().checked_mul(count));
+    if len.is_none_or(|len| len > MAX_REPEAT_LEN) {
+        return Err(RuntimeError::InvalidArgument(format!(
+            "repeat would build a string longer than {} bytes",
+            MAX_REPEAT_LEN
+        )));
+    }
+    Ok(Value::String(s.repeat(count as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn positions_count_chars_not_bytes() {
+        assert_eq!(length(&[string("héllo wörld")]).unwrap(), Value::Int(11));
+        assert_eq!(length(&[string("🦀")]).unwrap(), Value::Int(1));
+        assert_eq!(substring(&[string("héllo wörld"), Value::Int(1), Value::Int(8)]).unwrap(), string("éllo wö"));
+        assert_eq!(substring(&[string("日本語"), Value::Int(2), Value::Int(3)]).unwrap(), string("語"));
+        assert!(matches!(substring(&[string("日本語"), Value::Int(0), Value::Int(4)]), Err(RuntimeError::InvalidArgument(_))));
+        assert_eq!(find(&[string("héllo wörld"), string("wö")]).unwrap(), Value::Int(6));
+        assert_eq!(find(&[string("日本語"), string("語")]).unwrap(), Value::Int(2));
+        assert_eq!(find(&[string("日本語"), string("x")]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn repeat_is_capped() {
+        assert_eq!(repeat(&[string("ab"), Value::Int(3)]).unwrap(), string("ababab"));
+        assert!(matches!(repeat(&[string("x"), Value::Int(MAX_REPEAT_LEN as i64)]), Ok(Value::String(s)) if s.len() == MAX_REPEAT_LEN));
+        for count in [MAX_REPEAT_LEN as i64 + 1, i64::MAX, -1] {
+            assert!(matches!(repeat(&[string("x"), Value::Int(count)]), Err(RuntimeError::InvalidArgument(_))), "{}", count);
+        }
+        assert!(matches!(repeat(&[string("é"), Value::Int(MAX_REPEAT_LEN as i64 / 2 + 1)]), Err(RuntimeError::InvalidArgument(_))));
+    }
+}
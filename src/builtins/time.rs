@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::ast::*;
+use crate::vm::RuntimeError;
+use super::{expect_int, expect_number};
+
+/// Seconds since the Unix epoch, with sub-second precision
+pub fn now() -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError::Io(format!("system clock is before the Unix epoch: {}", e)))?;
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+/// Seconds since `start` on a clock that never goes backwards
+pub fn clock(start: Instant) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(start.elapsed().as_secs_f64()))
+}
+
+pub fn sleep(args: &[Value]) -> Result<Value, RuntimeError> {
+    let millis = expect_int(&args[0])?;
+    let millis = u64::try_from(millis)
+        .map_err(|_| RuntimeError::InvalidArgument(format!("sleep duration must not be negative, got {}", millis)))?;
+    std::thread::sleep(Duration::from_millis(millis));
+    Ok(Value::Null)
+}
+
+/// Format a Unix timestamp as ISO-8601 in UTC, e.g. `2024-05-01T13:45:00.250Z`
+pub fn format_time(args: &[Value]) -> Result<Value, RuntimeError> {
+    let timestamp = expect_number(&args[0])?;
+    iso8601(timestamp)
+        .map(Value::String)
+        .ok_or_else(|| RuntimeError::InvalidArgument(format!("timestamp {} is out of range", timestamp)))
+}
+
+/// ISO-8601 text for a Unix timestamp, or `None` outside the years 0..=9999
+pub fn iso8601(timestamp: f64) -> Option<String> {
+    let total_millis = (timestamp * 1000.0).floor();
+    // Years 0..=9999 keep the four-digit year format valid
+    if !(-62_167_219_200_000.0..253_402_300_800_000.0).contains(&total_millis) {
+        return None;
+    }
+    let total_millis = total_millis as i64;
+    let days = total_millis.div_euclid(86_400_000);
+    let millis_of_day = total_millis.rem_euclid(86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute) = (millis_of_day / 3_600_000, millis_of_day / 60_000 % 60);
+    let (second, millis) = (millis_of_day / 1000 % 60, millis_of_day % 1000);
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, hour, minute, second, millis
+    ))
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian calendar.
+/// Algorithm from Howard Hinnant's "chrono-Compatible Low-Level Date Algorithms".
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso8601_dates() {
+        assert_eq!(iso8601(0.0).unwrap(), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(1_709_209_815.25).unwrap(), "2024-02-29T12:30:15.250Z");
+        assert_eq!(iso8601(1_709_251_199.999).unwrap(), "2024-02-29T23:59:59.999Z");
+        assert_eq!(iso8601(-1.5).unwrap(), "1969-12-31T23:59:58.500Z");
+        assert_eq!(iso8601(-14_182_940.0).unwrap(), "1969-07-20T20:17:40.000Z");
+        assert_eq!(iso8601(-2_208_988_800.0).unwrap(), "1900-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(-62_167_219_200.0).unwrap(), "0000-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(-62_167_219_200.001), None);
+        assert_eq!(iso8601(253_402_300_800.0), None);
+    }
+}
@@ -2,7 +2,6 @@ use std::fs::File;
 use std::io::Write;
 use crate::ir::*;
 use crate::build::BYTECODE_VERSION;
-use bincode;
 
 /// Generate binary bytecode from IR
 /// This is the only stage that should know about binary format
@@ -16,7 +15,7 @@ pub fn emit_bytecode(program: &Program, output_path: &std::path::Path) -> std::i
     
     // Serialize to binary
     let binary_data = bincode::serialize(&bytecode)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Serialization error: {}", e)))?;
+        .map_err(|e| std::io::Error::other(format!("Serialization error: {}", e)))?;
     
     // Write to file
     let mut file = File::create(output_path)?;
@@ -25,6 +24,21 @@ pub fn emit_bytecode(program: &Program, output_path: &std::path::Path) -> std::i
     Ok(binary_data.len())
 }
 
+/// Human-readable listing of a program: its symbols and strings, then one
+/// line per instruction with its index and source position
+pub fn disassemble(program: &Program) -> String {
+    let mut lines = vec![format!("; {} (bytecode version {})", program.source_file, BYTECODE_VERSION)];
+    lines.push("; symbols".to_string());
+    lines.extend(program.symbol_table.iter().map(|symbol| format!(";   {:>4}  {}  {:?}", symbol.id, symbol.name, symbol.kind)));
+    lines.push("; strings".to_string());
+    lines.extend(program.string_table.iter().enumerate().map(|(index, string)| format!(";   {:>4}  {:?}", index, string)));
+    for (pc, instruction) in program.instructions.iter().enumerate() {
+        let position = program.spans.get(pc).map_or(String::new(), |span| format!("{}:{}", span.line, span.column));
+        lines.push(format!("{:>5}  {:<8} {:?}", pc, position, instruction));
+    }
+    lines.join("\n") + "\n"
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct Bytecode {
     version: u32,
@@ -53,3 +67,96 @@ pub fn load_bytecode(input_path: &std::path::Path) -> std::io::Result<Program> {
     Ok(bytecode.program)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{ArithmeticOp, DataType, Span, Value};
+    use crate::builtins::Builtin;
+
+    /// A program using every kind of value, type, instruction, builtin and log level
+    fn sample_program() -> Program {
+        let values = vec![
+            Value::String("s".into()),
+            Value::Number(1.5),
+            Value::Int(-2),
+            Value::Bool(true),
+            Value::Array(vec![Value::Null]),
+            Value::Map(vec![("k".into(), Value::Int(1))]),
+            Value::Null,
+        ];
+        let data_types = vec![
+            DataType::String,
+            DataType::Number,
+            DataType::Int,
+            DataType::Bool,
+            DataType::Any,
+            DataType::Int.array(),
+            DataType::Number.nullable(),
+        ];
+        let mut instructions: Vec<Instruction> = values.into_iter().map(|value| Instruction::LoadValue { value }).collect();
+        instructions.extend([
+            Instruction::InitVar { symbol_id: 0, value: Value::Null },
+            Instruction::SetVar { symbol_id: 0, value: Value::Number(1.0) },
+            Instruction::SetVarFromStack { symbol_id: 0 },
+            Instruction::DeclareFunc { symbol_id: 1, param_count: 1, param_symbol_ids: vec![2], body_start: 1, body_end: 2 },
+            Instruction::CallFunc { symbol_id: 1, arg_count: 1 },
+            Instruction::SpawnTask { symbol_id: 1, arg_count: 1 },
+            Instruction::LoadVar { symbol_id: 0 },
+            Instruction::Concat,
+            Instruction::JumpIfNotNull { target: 3 },
+            Instruction::Return,
+            Instruction::EnterTry { catch_start: 4, error_symbol_id: 3 },
+            Instruction::ExitTry { catch_end: 5 },
+            Instruction::Pop,
+            Instruction::Exit,
+        ]);
+        instructions.extend([ArithmeticOp::Add, ArithmeticOp::Subtract, ArithmeticOp::Multiply, ArithmeticOp::Divide, ArithmeticOp::Remainder]
+            .map(|op| Instruction::Arithmetic { op }));
+        instructions.extend(Builtin::ALL.iter().map(|&builtin| Instruction::CallBuiltin { builtin, arg_count: 0 }));
+        instructions.extend(LogType::ALL.iter().map(|&log_type| Instruction::Log {
+            log_type,
+            fields: vec!["x".into()],
+            message_expr_start: 0,
+            message_expr_end: 1,
+        }));
+        let mut symbol_table: Vec<Symbol> = data_types.into_iter().enumerate()
+            .map(|(id, data_type)| Symbol { id: id as u32, name: format!("v{}", id), kind: SymbolKind::Variable { data_type } })
+            .collect();
+        symbol_table.push(Symbol {
+            id: 7,
+            name: "f".into(),
+            kind: SymbolKind::Function { param_types: vec![DataType::Number], return_type: Some(DataType::Bool) },
+        });
+        Program {
+            source_file: "sample.q".into(),
+            spans: vec![Span { file_id: 0, start: 1, end: 2, line: 3, column: 4 }; instructions.len()],
+            instructions,
+            string_table: vec!["s".into()],
+            symbol_table,
+        }
+    }
+
+    /// FNV-1a, which is stable across Rust versions unlike `DefaultHasher`
+    fn fingerprint(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+    }
+
+    /// Packages only record the version, so any change to the encoding, such as
+    /// a variant inserted in the middle of an enum, must come with a new version.
+    /// When this fails, bump BYTECODE_VERSION and update both numbers here.
+    #[test]
+    fn encoding_changes_bump_the_version() {
+        let bytes = bincode::serialize(&sample_program()).unwrap();
+        assert_eq!((BYTECODE_VERSION, fingerprint(&bytes)), (17, 0xb119_6627_b419_bc75));
+    }
+
+    #[test]
+    fn bytecode_round_trips_through_a_file() {
+        let path = std::env::temp_dir().join(format!("quentin-codegen-{}.qbin", std::process::id()));
+        let size = emit_bytecode(&sample_program(), &path).unwrap();
+        let program = load_bytecode(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(size, bincode::serialize(&Bytecode { version: BYTECODE_VERSION, program: sample_program() }).unwrap().len());
+        assert_eq!(format!("{:?}", program), format!("{:?}", sample_program()));
+    }
+}
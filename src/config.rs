@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::diagnostic::{Diagnostic, Source};
+use crate::ir::LogType;
+use crate::log::{LogFormat, SinkConfig};
+
+/// Optional project settings, read from the directory `quentin` is run in
+pub const CONFIG_FILE: &str = "quentin.json";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub log: LogConfig,
+}
+
+/// Defaults for `quentin run`; command-line options take precedence
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: Option<LogType>,
+    pub format: Option<LogFormat>,
+    pub sinks: Vec<SinkConfig>,
+    /// Append `system.log` arguments to text log lines
+    pub fields: bool,
+}
+
+/// A `quentin.json` that cannot be read or does not describe a valid config
+pub struct ConfigError {
+    pub diagnostic: Diagnostic,
+    /// The file's text, for quoting the offending line
+    pub source: Source,
+}
+
+impl ProjectConfig {
+    /// Load `quentin.json` if it exists
+    pub fn load() -> Result<Self, Box<ConfigError>> {
+        let path = Path::new(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| {
+            Box::new(ConfigError {
+                diagnostic: Diagnostic::error("E0401", format!("cannot read {}: {}", CONFIG_FILE, e)),
+                source: Source::new(""),
+            })
+        })?;
+        Self::parse(&text)
+    }
+
+    /// Parse the text of a `quentin.json`
+    fn parse(text: &str) -> Result<Self, Box<ConfigError>> {
+        serde_json::from_str(text).map_err(|e| {
+            let source = Source::new(text);
+            // serde_json appends the position to its message; it is shown in the snippet instead
+            let message = e.to_string();
+            let message = message.rfind(" at line ").map_or(message.as_str(), |end| &message[..end]).to_string();
+            let start = offset(text, e.line(), e.column());
+            let diagnostic = Diagnostic::error("E0401", format!("invalid {}: {}", CONFIG_FILE, message))
+                .with_span(source.span(0, start, start));
+            Box::new(ConfigError { diagnostic, source })
+        })
+    }
+}
+
+/// Byte offset of a 1-based line and column, clamped to the text
+fn offset(text: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+    let line_text = text[line_start..].lines().next().unwrap_or_default();
+    let column_offset: usize = line_text.chars().take(column.saturating_sub(1)).map(char::len_utf8).sum();
+    (line_start + column_offset).min(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::SinkTarget;
+
+    fn parse(text: &str) -> ProjectConfig {
+        ProjectConfig::parse(text).unwrap_or_else(|err| panic!("{}", err.diagnostic.message))
+    }
+
+    /// Message and line of the error `text` is rejected with
+    fn error(text: &str) -> (String, u32) {
+        match ProjectConfig::parse(text) {
+            Ok(config) => panic!("accepted: {:?}", config),
+            Err(err) => (err.diagnostic.message, err.diagnostic.span.unwrap().line),
+        }
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = parse(r#"{ "log": { "level": "debug", "sinks": [ { "target": { "file": "app.log" }, "format": "json" } ] } }"#);
+        assert_eq!(config.log.level, Some(LogType::Debug));
+        assert_eq!(config.log.format, None);
+        assert_eq!(config.log.sinks, [SinkConfig {
+            format: Some(LogFormat::Json),
+            ..SinkConfig::new(SinkTarget::File("app.log".into()))
+        }]);
+        assert!(parse("{}").log.sinks.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_reported_at_their_line() {
+        let errors: Vec<(String, u32)> = [
+            "{\n  \"log\": {\n    \"level\": \"loud\"\n  }\n}",
+            "{ \"log\": { \"format\": \"xml\" } }",
+            "{\n  \"logs\": {}\n}",
+            "{ \"log\": { \"sinks\": [ { \"target\": \"syslog\" } ] } }",
+            "{ \"log\": { \"sinks\": [ { \"target\": \"stdout\", \"colour\": true } ] } }",
+            "{ \"log\": \n",
+        ].into_iter().map(error).collect();
+        let expected = [
+            ("unknown variant `loud`, expected one of `trace`, `debug`, `info`, `warn`, `error`, `fatal`", 3),
+            ("unknown variant `xml`, expected one of `text`, `json`, `logfmt`", 1),
+            ("unknown field `logs`, expected `log`", 2),
+            ("unknown variant `syslog`, expected one of `stdout`, `stderr`, `file`", 1),
+            ("unknown field `colour`, expected one of `target`, `level`, `max_level`, `format`", 1),
+            ("EOF while parsing a value", 2),
+        ];
+        let expected: Vec<(String, u32)> = expected.iter()
+            .map(|(message, line)| (format!("invalid {}: {}", CONFIG_FILE, message), *line))
+            .collect();
+        assert_eq!(errors, expected);
+    }
+}
@@ -0,0 +1,231 @@
+use pest::iterators::Pair;
+use crate::diagnostic::Source;
+use crate::parser::{parse_source, Rule};
+
+/// Concrete syntax tree: the parse tree together with everything pest skips,
+/// such as whitespace, comments, keywords and punctuation. Printing it gives
+/// back the source byte for byte, so tools can rewrite part of a file and
+/// leave the rest as written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstNode {
+    pub rule: Rule,
+    /// Byte range of the node in the source
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<CstElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CstElement {
+    Node(CstNode),
+    Token(CstToken),
+}
+
+/// A leaf of the tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    Comment,
+    /// Keywords and keys written in the grammar, such as `system.init` or `"name"`
+    Keyword,
+    /// Punctuation and operators written in the grammar, such as `{` or `=>`
+    Punct,
+    /// The whole text of a rule with no inner rules, such as an identifier or string
+    Text,
+}
+
+impl TokenKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Comment => "comment",
+            TokenKind::Keyword => "keyword",
+            TokenKind::Punct => "punct",
+            TokenKind::Text => "text",
+        }
+    }
+
+    /// Whitespace and comments, which do not change what the program means
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+/// Parse source into a CST whose root covers the whole input
+pub fn parse_cst(source: &str) -> Result<CstNode, Box<pest::error::Error<Rule>>> {
+    let file = parse_source(source)?.next().unwrap();
+    Ok(build_node(file, source, 0, source.len()))
+}
+
+fn build_node(pair: Pair<Rule>, source: &str, start: usize, end: usize) -> CstNode {
+    let rule = pair.as_rule();
+    let mut children = Vec::new();
+    let mut inner = pair.into_inner().filter(|child| child.as_rule() != Rule::EOI).peekable();
+    if inner.peek().is_none() {
+        // Atomic rules such as identifiers are a single token
+        if start < end {
+            children.push(CstElement::Token(CstToken { kind: TokenKind::Text, start, text: source[start..end].to_string() }));
+        }
+        return CstNode { rule, start, end, children };
+    }
+    let mut pos = start;
+    for child in inner {
+        let span = child.as_span();
+        push_gap(&mut children, source, pos, span.start());
+        if child.as_rule() == Rule::comment {
+            children.push(CstElement::Token(CstToken { kind: TokenKind::Comment, start: span.start(), text: span.as_str().to_string() }));
+        } else {
+            children.push(CstElement::Node(build_node(child, source, span.start(), span.end())));
+        }
+        pos = span.end();
+    }
+    push_gap(&mut children, source, pos, end);
+    CstNode { rule, start, end, children }
+}
+
+/// Split text that no inner rule matched into whitespace, keyword and punctuation tokens
+fn push_gap(children: &mut Vec<CstElement>, source: &str, start: usize, end: usize) {
+    let gap = &source[start..end];
+    let mut pos = 0;
+    while pos < gap.len() {
+        let rest = &gap[pos..];
+        let first = rest.chars().next().unwrap();
+        let (kind, len) = if first.is_whitespace() {
+            (TokenKind::Whitespace, rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len()))
+        } else if first == '"' {
+            // Quoted keys such as `"name"`
+            (TokenKind::Keyword, rest[1..].find('"').map_or(rest.len(), |close| close + 2))
+        } else if first.is_ascii_alphanumeric() || first == '_' {
+            // `system.init` is one keyword, the dot in `x.value` is punctuation
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            (TokenKind::Keyword, len)
+        } else if rest.starts_with("=>") || rest.starts_with("??") || rest.starts_with("[]") {
+            (TokenKind::Punct, 2)
+        } else {
+            (TokenKind::Punct, first.len_utf8())
+        };
+        children.push(CstElement::Token(CstToken { kind, start: start + pos, text: rest[..len].to_string() }));
+        pos += len;
+    }
+}
+
+impl CstNode {
+    /// The source text of the node, exactly as written
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+
+    /// Child nodes, skipping tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &CstNode> {
+        self.children.iter().filter_map(|child| match child {
+            CstElement::Node(node) => Some(node),
+            CstElement::Token(_) => None,
+        })
+    }
+
+    /// Every token under the node, in source order
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        for child in &self.children {
+            match child {
+                CstElement::Node(node) => tokens.extend(node.tokens()),
+                CstElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    /// The first token that is not whitespace or a comment
+    pub fn first_token(&self) -> Option<&CstToken> {
+        self.tokens().into_iter().find(|token| !token.kind.is_trivia())
+    }
+}
+
+/// One line per token other than whitespace: its line and column, its kind
+/// and its text. Text tokens are named after their rule, such as `identifier`.
+pub fn token_listing(node: &CstNode, source: &Source) -> String {
+    let mut out = String::new();
+    list_tokens(node, source, &mut out);
+    out
+}
+
+fn list_tokens(node: &CstNode, source: &Source, out: &mut String) {
+    for child in &node.children {
+        match child {
+            CstElement::Node(child) => list_tokens(child, source, out),
+            CstElement::Token(token) if token.kind == TokenKind::Whitespace => {}
+            CstElement::Token(token) => {
+                let span = source.span(0, token.start, token.start + token.text.len());
+                let kind = match token.kind {
+                    TokenKind::Text => format!("{:?}", node.rule),
+                    kind => kind.name().to_string(),
+                };
+                let position = format!("{}:{}", span.line, span.column);
+                out.push_str(&format!("{:<8} {:<16} {:?}\n", position, kind, token.text));
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CstNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for child in &self.children {
+            match child {
+                CstElement::Node(node) => write!(f, "{}", node)?,
+                CstElement::Token(token) => f.write_str(&token.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_round_trips_through_the_cst() {
+        let sources = [
+            include_str!("../test.q"),
+            "// leading\nlet x: int = 1; // after\n\n   // indented\nlog info \"x\" & x.value;\n",
+            "system.log{\r\n    \"type\": info,\r\n    \"message\": \"crlf\"\r\n};\r\n",
+            "function f(a in int) {\n\treturn a.value;\n};\n\tcall f(a => 1);\t\n",
+            "let x: int = 1; // no newline at the end",
+            "",
+        ];
+        for source in sources {
+            let cst = parse_cst(source).unwrap_or_else(|err| panic!("{}\nin\n{}", err, source));
+            assert_eq!(cst.to_string(), source);
+        }
+    }
+
+    #[test]
+    fn token_listing_skips_whitespace_and_counts_columns_in_characters() {
+        let source = Source::new("let s: string = \"é\"; // note\nlog info s.value;\n");
+        let cst = parse_cst(&source.text).unwrap();
+        let expected = [
+            r#"1:1      let_keyword      "let""#,
+            r#"1:5      identifier       "s""#,
+            r#"1:6      punct            ":""#,
+            r#"1:8      datatype         "string""#,
+            r#"1:15     punct            "=""#,
+            r#"1:17     string           "\"é\"""#,
+            r#"1:20     punct            ";""#,
+            r#"1:22     comment          "// note""#,
+            r#"2:1      log_keyword      "log""#,
+            r#"2:5      log_type         "info""#,
+            r#"2:10     identifier       "s""#,
+            r#"2:11     punct            ".""#,
+            r#"2:12     keyword          "value""#,
+            r#"2:17     punct            ";""#,
+        ];
+        assert_eq!(token_listing(&cst, &source).lines().collect::<Vec<_>>(), expected);
+    }
+}
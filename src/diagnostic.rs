@@ -0,0 +1,194 @@
+use colored::*;
+use crate::ast::Span;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A message about the program, pointing at the source it refers to
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier such as `E0105`, listed in the README
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Short text printed next to the underline
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message.into())
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message.into())
+    }
+
+    fn new(severity: Severity, code: &'static str, message: String) -> Self {
+        Self { severity, code, message, span: None, label: None, notes: Vec::new(), help: Vec::new() }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Render the diagnostic for a terminal, without the trailing newline.
+    /// The source line is only shown when `source` is given.
+    ///
+    /// ```text
+    /// error[E0105]: type mismatch: cannot assign number to 's' of type string
+    ///  --> app.q:3:14
+    ///   |
+    /// 3 |     "value": 1.5
+    ///   |              ^^^ expected string
+    ///   |
+    ///   = help: ...
+    /// ```
+    pub fn render(&self, path: &str, source: Option<&Source>, color: bool) -> String {
+        let paint_severity = |text: &str| -> String {
+            match (color, self.severity) {
+                (false, _) => text.to_string(),
+                (true, Severity::Error) => text.red().bold().to_string(),
+                (true, Severity::Warning) => text.yellow().bold().to_string(),
+            }
+        };
+        let paint_gutter = |text: &str| -> String {
+            if color { text.blue().bold().to_string() } else { text.to_string() }
+        };
+        let message = if color { self.message.bold().to_string() } else { self.message.clone() };
+
+        let mut lines = vec![format!("{}: {}", paint_severity(&format!("{}[{}]", self.severity.name(), self.code)), message)];
+        let snippet = self.span.zip(source).and_then(|(span, source)| Some((span, source.line(span.line)?, source)));
+        let gutter_width = snippet.map_or(1, |(span, _, _)| span.line.to_string().len());
+        let pad = " ".repeat(gutter_width);
+        if let Some(span) = self.span {
+            lines.push(format!("{}{} {}:{}:{}", pad, paint_gutter("-->"), path, span.line, span.column));
+        }
+        if let Some((span, text, source)) = snippet {
+            let prefix: String = text.chars().take(span.column as usize - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            // Underline to the end of the span or of its first line, whichever comes first
+            let spanned = source.text.get(span.start..span.end).unwrap_or_default();
+            let width = spanned.lines().next().unwrap_or_default().chars().count().max(1);
+            let mut underline = paint_severity(&"^".repeat(width));
+            if let Some(label) = &self.label {
+                underline = format!("{} {}", underline, paint_severity(label));
+            }
+            lines.push(format!("{} {}", pad, paint_gutter("|")));
+            lines.push(format!("{} {} {}", paint_gutter(&span.line.to_string()), paint_gutter("|"), text));
+            lines.push(format!("{} {} {}{}", pad, paint_gutter("|"), prefix, underline));
+        }
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            if snippet.is_some() {
+                lines.push(format!("{} {}", pad, paint_gutter("|")));
+            }
+            for note in &self.notes {
+                lines.push(format!("{} {} note: {}", pad, paint_gutter("="), note));
+            }
+            for help in &self.help {
+                lines.push(format!("{} {} help: {}", pad, paint_gutter("="), help));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Source text with a line index, for turning byte offsets into line and column
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub text: String,
+    /// Byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl Source {
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self { text, line_starts }
+    }
+
+    /// Span of the bytes `start..end`, with the 1-based line and column of `start`
+    pub fn span(&self, file_id: u32, start: usize, end: usize) -> Span {
+        let line_index = self.line_starts.partition_point(|&line_start| line_start <= start) - 1;
+        let column = self.text[self.line_starts[line_index]..start].chars().count() + 1;
+        Span { file_id, start, end, line: line_index as u32 + 1, column: column as u32 }
+    }
+
+    /// Text of a 1-based line, without its line ending
+    pub fn line(&self, line: u32) -> Option<&str> {
+        let start = *self.line_starts.get((line as usize).checked_sub(1)?)?;
+        let end = self.line_starts.get(line as usize).copied().unwrap_or(self.text.len());
+        Some(self.text[start..end].trim_end_matches(['\n', '\r']))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_is_aligned_by_characters() {
+        let source = Source::new("let a = 1;\nlet s: string = \"日本é\" & x;\n");
+        let start = source.text.find('x').unwrap();
+        let diagnostic = Diagnostic::error("E0101", "undefined variable 'x'")
+            .with_span(source.span(0, start, start + 1))
+            .with_label("not found in this scope")
+            .with_help("declare 'x' before using it");
+        let expected = [
+            "error[E0101]: undefined variable 'x'",
+            " --> app.q:2:25",
+            "  |",
+            "2 | let s: string = \"日本é\" & x;",
+            "  |                         ^ not found in this scope",
+            "  |",
+            "  = help: declare 'x' before using it",
+        ].join("\n");
+        assert_eq!(diagnostic.render("app.q", Some(&source), false), expected);
+    }
+
+    #[test]
+    fn underline_stops_at_the_end_of_the_first_line_and_keeps_tabs() {
+        let source = Source::new("log info\t{ \"é\": 1,\n  \"b\": 2 };\n");
+        let start = source.text.find('{').unwrap();
+        let diagnostic = Diagnostic::warning("W0001", "unused value")
+            .with_span(source.span(0, start, source.text.len() - 2));
+        let rendered = diagnostic.render("app.q", Some(&source), false);
+        assert_eq!(rendered.lines().nth(4), Some("  |         \t^^^^^^^^^"));
+    }
+}
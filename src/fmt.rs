@@ -0,0 +1,255 @@
+use crate::cst::{parse_cst, CstElement, CstNode, TokenKind};
+use crate::diagnostic::{Diagnostic, Source};
+use crate::parser::{parse_program, Rule};
+
+/// Order of keys inside `system.*` blocks; keys not listed keep their place after these
+const KEY_ORDER: &[&str] = &[
+    "\"type\"", "\"name\"", "\"handle\"", "\"datatype\"", "\"value\"", "\"prompt\"", "\"code\"",
+    "arguments", "\"message\"", "parameters",
+];
+
+const INDENT: &str = "    ";
+
+/// Rewrite Q source in the canonical style: four-space indentation, one
+/// block key per line in a fixed order, single spaces around operators and
+/// a `;` after every statement. Comments and single blank lines between
+/// statements are kept. Returns the syntax errors if the source does not parse.
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let Ok(file) = parse_cst(source) else {
+        let (_, diagnostics) = parse_program(&Source::new(source), 0);
+        return Err(diagnostics);
+    };
+    let mut formatter = Formatter { out: String::new(), depth: 0 };
+    formatter.statements(&file);
+    let formatted = formatter.out.trim_start_matches('\n');
+    Ok(if formatted.is_empty() { String::new() } else { format!("{}\n", formatted.trim_end()) })
+}
+
+struct Formatter {
+    out: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn indent(&self) -> String {
+        INDENT.repeat(self.depth)
+    }
+
+    /// Statements and comments, one per line. A comment on the same line as
+    /// the statement before it stays there; runs of blank lines become one.
+    fn statements(&mut self, node: &CstNode) {
+        let mut first = true;
+        let mut newlines = 0;
+        for child in &node.children {
+            let comment = match child {
+                CstElement::Token(token) if token.kind == TokenKind::Whitespace => {
+                    newlines += token.text.matches('\n').count();
+                    continue;
+                }
+                CstElement::Token(token) if token.kind == TokenKind::Comment => Some(token.text.trim_end()),
+                CstElement::Node(node) if node.rule == Rule::statement => None,
+                _ => continue,
+            };
+            if !first {
+                if comment.is_some() && newlines == 0 {
+                    self.out.push(' ');
+                    self.out.push_str(comment.unwrap_or_default());
+                    continue;
+                }
+                if newlines >= 2 {
+                    self.out.push('\n');
+                }
+            }
+            self.out.push('\n');
+            self.out.push_str(&self.indent());
+            match (comment, child) {
+                (Some(comment), _) => self.out.push_str(comment),
+                (None, CstElement::Node(statement)) => self.statement(statement.nodes().next().unwrap()),
+                (None, CstElement::Token(_)) => {}
+            }
+            first = false;
+            newlines = 0;
+        }
+    }
+
+    /// `{`, the indented statements and the closing `}` on its own line
+    fn body(&mut self, pair: &CstNode) {
+        self.out.push('{');
+        self.depth += 1;
+        self.statements(pair);
+        self.depth -= 1;
+        self.out.push('\n');
+        self.out.push_str(&self.indent());
+        self.out.push('}');
+    }
+
+    fn statement(&mut self, pair: &CstNode) {
+        match pair.rule {
+            Rule::system_init | Rule::system_set | Rule::system_log | Rule::system_input
+            | Rule::system_exit | Rule::system_exec => {
+                let keyword = pair.text().split('{').next().unwrap_or_default().trim().to_string();
+                let pairs = pair.nodes().next().map(|pairs| pairs.nodes().collect()).unwrap_or_default();
+                self.block(&keyword, pairs);
+                self.out.push(';');
+            }
+            Rule::function_decl => {
+                let mut parts = pair.nodes();
+                let name = parts.next().unwrap().text();
+                let params: Vec<String> = parts.next().unwrap().nodes()
+                    .map(|param| {
+                        let mut param = param.nodes();
+                        format!("{} in {}", param.next().unwrap().text(), param.next().unwrap().text())
+                    })
+                    .collect();
+                self.out.push_str(&format!("function {}({}) ", name, params.join(", ")));
+                self.body(parts.next().unwrap());
+                self.out.push(';');
+            }
+            Rule::try_statement => {
+                let mut parts = pair.nodes();
+                self.out.push_str("try ");
+                self.body(parts.next().unwrap());
+                self.out.push_str(&format!(" catch ({}) ", parts.next().unwrap().text()));
+                self.body(parts.next().unwrap());
+                self.out.push(';');
+            }
+            Rule::return_statement => {
+                let value = expression(pair.nodes().nth(1).unwrap());
+                self.out.push_str(&format!("return {};", value));
+            }
+            Rule::call_statement => {
+                let call = expression(pair.nodes().next().unwrap());
+                self.out.push_str(&format!("{};", call));
+            }
+            Rule::let_statement => {
+                let mut parts = pair.nodes().skip(1);
+                let name = parts.next().unwrap().text();
+                let data_type = parts.next().unwrap().text();
+                match parts.next() {
+                    Some(value) => self.out.push_str(&format!("let {}: {} = {};", name, data_type, expression(value))),
+                    None => self.out.push_str(&format!("let {}: {};", name, data_type)),
+                }
+            }
+            Rule::assign_statement => {
+                let mut parts = pair.nodes();
+                let name = parts.next().unwrap().text();
+                self.out.push_str(&format!("{} = {};", name, expression(parts.next().unwrap())));
+            }
+            Rule::log_statement => {
+                let mut parts = pair.nodes().skip(1);
+                let log_type = parts.next().unwrap().text();
+                self.out.push_str(&format!("log {} {};", log_type, expression(parts.next().unwrap())));
+            }
+            Rule::call_function => {
+                let mut parts = pair.nodes().skip(1);
+                let name = parts.next().unwrap().text();
+                let params = exec_params(parts.next().unwrap());
+                self.out.push_str(&format!("call {}({});", name, params.join(", ")));
+            }
+            // Includes are not implemented yet; keep them as written
+            _ => self.out.push_str(pair.text().trim()),
+        }
+    }
+
+    /// A `system.*` block with one key per line, in `KEY_ORDER`
+    fn block(&mut self, keyword: &str, mut pairs: Vec<&CstNode>) {
+        if pairs.is_empty() {
+            self.out.push_str(&format!("{}{{}}", keyword));
+            return;
+        }
+        pairs.sort_by_key(|pair| KEY_ORDER.iter().position(|name| *name == key(pair)).unwrap_or(KEY_ORDER.len()));
+        self.out.push_str(&format!("{}{{", keyword));
+        self.depth += 1;
+        let indent = self.indent();
+        let entries: Vec<String> = pairs.into_iter().map(|pair| format!("\n{}{}", indent, self.entry(pair))).collect();
+        self.depth -= 1;
+        self.out.push_str(&entries.join(","));
+        self.out.push('\n');
+        self.out.push_str(&self.indent());
+        self.out.push('}');
+    }
+
+    /// One `key: value` entry of a block
+    fn entry(&self, pair: &CstNode) -> String {
+        let key = key(pair).to_string();
+        let Some(value) = pair.nodes().next() else {
+            return key;
+        };
+        match value.rule {
+            Rule::arguments => self.list(&key, value.nodes().map(expression).collect()),
+            Rule::exec_params => self.list(&key, exec_params(value)),
+            Rule::expression => format!("{}: {}", key, expression(value)),
+            _ => format!("{}: {}", key, value.text()),
+        }
+    }
+
+    /// `arguments{ ... }` and `parameters{ ... }`, one item per line
+    fn list(&self, key: &str, items: Vec<String>) -> String {
+        if items.is_empty() {
+            return format!("{}{{}}", key);
+        }
+        let inner = format!("{}{}", self.indent(), INDENT);
+        let items: Vec<String> = items.iter().map(|item| format!("\n{}{}", inner, item)).collect();
+        format!("{}{{{}\n{}}}", key, items.join(","), self.indent())
+    }
+}
+
+/// The key of a block entry, quoted or bare
+fn key(pair: &CstNode) -> &str {
+    pair.first_token().map_or("", |token| token.text.as_str())
+}
+
+/// `name => expression` parameters of a function call
+fn exec_params(pair: &CstNode) -> Vec<String> {
+    pair.nodes()
+        .map(|param| {
+            let mut parts = param.nodes();
+            let name = parts.next().unwrap().text();
+            format!("{} => {}", name, expression(parts.next().unwrap()))
+        })
+        .collect()
+}
+
+/// An expression with single spaces around operators. Parentheses are kept
+/// as written, since they may be needed for precedence.
+fn expression(pair: &CstNode) -> String {
+    match pair.rule {
+        Rule::expression | Rule::coalesce | Rule::sum | Rule::product => {
+            let separator = match pair.rule {
+                Rule::expression => Some(" & "),
+                Rule::coalesce => Some(" ?? "),
+                _ => None,
+            };
+            let mut out = String::new();
+            for (index, part) in pair.nodes().enumerate() {
+                match part.rule {
+                    Rule::add_op | Rule::mul_op => out.push_str(&format!(" {} ", part.text())),
+                    _ => {
+                        if let (Some(separator), true) = (separator, index > 0) {
+                            out.push_str(separator);
+                        }
+                        out.push_str(&operand(part));
+                    }
+                }
+            }
+            out
+        }
+        _ => operand(pair),
+    }
+}
+
+/// A term; a nested `expression` here was written in parentheses
+fn operand(pair: &CstNode) -> String {
+    match pair.rule {
+        Rule::expression => format!("({})", expression(pair)),
+        Rule::builtin_call => {
+            let mut parts = pair.nodes();
+            let name = parts.next().unwrap().text();
+            let args: Vec<String> = parts.next().unwrap().nodes().map(expression).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        Rule::argument => pair.text().split_whitespace().collect(),
+        Rule::value => pair.text().to_string(),
+        _ => expression(pair),
+    }
+}
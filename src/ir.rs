@@ -1,11 +1,18 @@
 use serde::{Serialize, Deserialize};
 use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::diagnostic::Diagnostic;
+use std::collections::HashMap;
 
 /// Intermediate Representation - a lower-level representation
 /// that is independent of the source syntax and suitable for code generation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
+    /// Path of the source file, as given to `quentin build`
+    pub source_file: String,
     pub instructions: Vec<Instruction>,
+    /// Source location of each instruction, parallel to `instructions`
+    pub spans: Vec<Span>,
     pub string_table: Vec<String>,
     pub symbol_table: Vec<Symbol>,
 }
@@ -20,24 +27,85 @@ pub enum Instruction {
     // Function operations
     DeclareFunc { symbol_id: u32, param_count: u32, param_symbol_ids: Vec<u32>, body_start: u32, body_end: u32 },
     CallFunc { symbol_id: u32, arg_count: u32 },
+    /// Start a function as a new task and push its id
+    SpawnTask { symbol_id: u32, arg_count: u32 },
+    CallBuiltin { builtin: Builtin, arg_count: u32 },
     
     // Expression operations
     LoadValue { value: Value },
     LoadVar { symbol_id: u32 },
     Concat,
+    Arithmetic { op: ArithmeticOp },
+    /// Leave the top of the stack and jump if it is not null, otherwise pop it
+    JumpIfNotNull { target: u32 },
     
     // System operations
-    Log { log_type: LogType, message_expr_start: u32, message_expr_end: u32 },
+    /// Log the message on top of the stack; the values of `fields` are below it, in order
+    Log { log_type: LogType, fields: Vec<String>, message_expr_start: u32, message_expr_end: u32 },
     
     // Control flow
     Return,
+    /// Install an error handler; runtime errors store their message and jump to `catch_start`
+    EnterTry { catch_start: u32, error_symbol_id: u32 },
+    /// Remove the innermost handler and skip its catch block
+    ExitTry { catch_end: u32 },
+    /// Discard the top of the stack
+    Pop,
+    /// Stop the program with the exit code on top of the stack
+    Exit,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Severity of a `system.log` message, from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogType {
+    Trace,
+    Debug,
     Info,
     Warn,
     Error,
+    /// Logged, then the program stops with `FATAL_EXIT_CODE`
+    Fatal,
+}
+
+impl LogType {
+    pub const ALL: &'static [LogType] = &[
+        LogType::Trace,
+        LogType::Debug,
+        LogType::Info,
+        LogType::Warn,
+        LogType::Error,
+        LogType::Fatal,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogType::Trace => "trace",
+            LogType::Debug => "debug",
+            LogType::Info => "info",
+            LogType::Warn => "warn",
+            LogType::Error => "error",
+            LogType::Fatal => "fatal",
+        }
+    }
+}
+
+impl std::str::FromStr for LogType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        LogType::ALL.iter().copied().find(|log_type| log_type.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = LogType::ALL.iter().map(|log_type| log_type.name()).collect();
+            format!("unknown log level '{}', expected one of {}", s, names.join(", "))
+        })
+    }
+}
+
+impl std::fmt::Display for LogType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,45 +121,31 @@ pub enum SymbolKind {
     Function { param_types: Vec<DataType>, return_type: Option<DataType> },
 }
 
-/// Convert AST to IR
-pub fn ast_to_ir(ast: &[AstNode]) -> Program {
-    let mut instructions = Vec::new();
-    let string_table = Vec::new();
-    let mut symbol_table = Vec::new();
-    let mut symbol_counter = 0u32;
-    
-    // First pass: collect all symbols
-    let mut symbol_map = std::collections::HashMap::new();
+/// Function called automatically after top-level statements, if declared
+pub const ENTRY_POINT: &str = "main";
+
+/// Convert AST to IR. The AST must have passed semantic analysis; a name
+/// that still cannot be resolved is reported as an internal compiler error.
+pub fn ast_to_ir(ast: &[AstNode], source_file: &str) -> Result<Program, Vec<Diagnostic>> {
+    let mut builder = IrBuilder::default();
+    let mut scope = Scope::new();
     
+    // First pass: collect all top-level symbols
     for node in ast {
         match node {
             AstNode::Statement(stmt) => {
-                match stmt {
-                    Statement::SystemInit(var_decl) => {
-                        let symbol_id = symbol_counter;
-                        symbol_counter += 1;
-                        symbol_map.insert(var_decl.name.clone(), symbol_id);
-                        symbol_table.push(Symbol {
-                            id: symbol_id,
-                            name: var_decl.name.clone(),
-                            kind: SymbolKind::Variable {
-                                data_type: var_decl.data_type.clone(),
-                            },
-                        });
+                match &stmt.kind {
+                    StatementKind::SystemInit(var_decl) => {
+                        builder.declare_variable(&var_decl.name, &var_decl.data_type, &mut scope);
                     }
-                    Statement::FunctionDeclaration(func_decl) => {
-                        let symbol_id = symbol_counter;
-                        symbol_counter += 1;
-                        symbol_map.insert(func_decl.name.clone(), symbol_id);
+                    StatementKind::FunctionDeclaration(func_decl) => {
                         let param_types: Vec<DataType> = func_decl.params.iter().map(|(_, dt)| dt.clone()).collect();
-                        symbol_table.push(Symbol {
-                            id: symbol_id,
-                            name: func_decl.name.clone(),
-                            kind: SymbolKind::Function {
-                                param_types,
-                                return_type: None, // TODO: infer return type
-                            },
-                        });
+                        let param_names = func_decl.params.iter().map(|(name, _)| name.clone()).collect();
+                        builder.params.insert(func_decl.name.clone(), param_names);
+                        builder.declare(&func_decl.name, SymbolKind::Function {
+                            param_types,
+                            return_type: None, // TODO: infer return type
+                        }, &mut scope);
                     }
                     _ => {}
                 }
@@ -102,172 +156,340 @@ pub fn ast_to_ir(ast: &[AstNode]) -> Program {
     // Second pass: generate instructions
     for node in ast {
         match node {
-            AstNode::Statement(stmt) => {
-                match stmt {
-                    Statement::SystemInit(var_decl) => {
-                        let symbol_id = symbol_map[&var_decl.name];
-                        let value = var_decl.value.as_ref()
-                            .map(|expr| expression_to_value(expr))
-                            .unwrap_or(Value::Null);
-                        instructions.push(Instruction::InitVar { symbol_id, value });
-                    }
-                    Statement::SystemSet(var_assign) => {
-                        let symbol_id = symbol_map[&var_assign.name];
-                        // Evaluate expression and leave result on stack
-                        expression_to_instructions(&var_assign.value, &mut instructions, &symbol_map);
-                        instructions.push(Instruction::SetVarFromStack { symbol_id });
+            AstNode::Statement(stmt) => builder.statement(stmt, &mut scope),
+        }
+    }
+    
+    // Entry point: call `main` once top-level initialisation has run
+    let main = ast.iter().find_map(|node| match node {
+        AstNode::Statement(Statement { kind: StatementKind::FunctionDeclaration(func_decl), .. }) if func_decl.name == ENTRY_POINT => Some(func_decl),
+        _ => None,
+    });
+    if let Some(main) = main {
+        let Some(symbol_id) = builder.symbol(ENTRY_POINT, &scope) else {
+            return Err(builder.errors);
+        };
+        let arg_count = main.params.len() as u32;
+        if arg_count == 1 {
+            builder.emit(Instruction::CallBuiltin { builtin: Builtin::Args, arg_count: 0 });
+        }
+        builder.emit(Instruction::CallFunc { symbol_id, arg_count });
+    }
+    
+    if !builder.errors.is_empty() {
+        return Err(builder.errors);
+    }
+    Ok(Program {
+        source_file: source_file.to_string(),
+        instructions: builder.instructions,
+        spans: builder.spans,
+        string_table: Vec::new(),
+        symbol_table: builder.symbol_table,
+    })
+}
+
+/// Maps names visible at a point of the program to symbol IDs
+type Scope = HashMap<String, u32>;
+
+#[derive(Default)]
+struct IrBuilder {
+    instructions: Vec<Instruction>,
+    spans: Vec<Span>,
+    symbol_table: Vec<Symbol>,
+    symbol_counter: u32,
+    /// Parameter names of each function, in declaration order
+    params: HashMap<String, Vec<String>>,
+    /// Span of the innermost statement or expression being lowered
+    span: Span,
+    /// Names that could not be resolved
+    errors: Vec<Diagnostic>,
+}
+
+impl IrBuilder {
+    /// Allocate a new symbol and make it visible in `scope`
+    fn declare(&mut self, name: &str, kind: SymbolKind, scope: &mut Scope) -> u32 {
+        let symbol_id = self.symbol_counter;
+        self.symbol_counter += 1;
+        scope.insert(name.to_string(), symbol_id);
+        self.symbol_table.push(Symbol {
+            id: symbol_id,
+            name: name.to_string(),
+            kind,
+        });
+        symbol_id
+    }
+
+    /// Symbol ID of a name, reporting it at the current span if it is not in scope
+    fn symbol(&mut self, name: &str, scope: &Scope) -> Option<u32> {
+        let symbol_id = scope.get(name).copied();
+        if symbol_id.is_none() {
+            self.internal_error(format!("'{}' has no symbol", name));
+        }
+        symbol_id
+    }
+
+    /// Report something semantic analysis should have ruled out
+    fn internal_error(&mut self, message: String) {
+        self.errors.push(
+            Diagnostic::error("E0301", format!("internal compiler error: {}", message))
+                .with_span(self.span)
+                .with_note("semantic analysis accepted this code; this is a compiler bug, please report it"),
+        );
+    }
+
+    fn variable_type(&self, symbol_id: u32) -> Option<&DataType> {
+        self.symbol_table.iter().find(|symbol| symbol.id == symbol_id).and_then(|symbol| match &symbol.kind {
+            SymbolKind::Variable { data_type } => Some(data_type),
+            _ => None,
+        })
+    }
+
+    fn declare_variable(&mut self, name: &str, data_type: &DataType, scope: &mut Scope) -> u32 {
+        self.declare(name, SymbolKind::Variable { data_type: data_type.clone() }, scope)
+    }
+
+    /// Index the next instruction will be emitted at
+    fn position(&self) -> u32 {
+        self.instructions.len() as u32
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+        self.spans.push(self.span);
+    }
+
+    fn block(&mut self, stmts: &[Statement], scope: &Scope) {
+        // Declarations inside a block are not visible after it
+        let mut block_scope = scope.clone();
+        for stmt in stmts {
+            self.statement(stmt, &mut block_scope);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement, scope: &mut Scope) {
+        let outer = std::mem::replace(&mut self.span, stmt.span);
+        self.statement_kind(&stmt.kind, scope);
+        self.span = outer;
+    }
+
+    fn statement_kind(&mut self, kind: &StatementKind, scope: &mut Scope) {
+        match kind {
+            StatementKind::SystemInit(var_decl) => {
+                // Top-level variables were declared in the first pass
+                let symbol_id = match scope.get(&var_decl.name) {
+                    Some(&symbol_id) => symbol_id,
+                    None => self.declare_variable(&var_decl.name, &var_decl.data_type, scope),
+                };
+                match &var_decl.value {
+                    Some(Expression { kind: ExpressionKind::Value(value), .. }) => {
+                        self.emit(Instruction::InitVar { symbol_id, value: value.clone() });
                     }
-                    Statement::SystemLog(log) => {
-                        let log_type = match log.log_type.to_lowercase().as_str() {
-                            "info" => LogType::Info,
-                            "warn" => LogType::Warn,
-                            "error" => LogType::Error,
-                            _ => LogType::Info,
-                        };
-                        let expr_start = instructions.len() as u32;
-                        expression_to_instructions(&log.message, &mut instructions, &symbol_map);
-                        let expr_end = instructions.len() as u32;
-                        instructions.push(Instruction::Log { log_type, message_expr_start: expr_start, message_expr_end: expr_end });
+                    Some(expr) => {
+                        // Runtime initialiser: declare first, then assign the evaluated expression
+                        self.emit(Instruction::InitVar { symbol_id, value: Value::Null });
+                        self.expression(expr, scope);
+                        self.emit(Instruction::SetVarFromStack { symbol_id });
                     }
-                    Statement::FunctionDeclaration(func_decl) => {
-                        let symbol_id = symbol_map[&func_decl.name];
-                        
-                        // Create symbol IDs for function parameters (they need their own scope)
-                        let mut param_symbol_ids = Vec::new();
-                        let mut func_symbol_map = symbol_map.clone();
-                        for (param_name, _) in &func_decl.params {
-                            let param_symbol_id = symbol_counter;
-                            symbol_counter += 1;
-                            param_symbol_ids.push(param_symbol_id);
-                            func_symbol_map.insert(param_name.clone(), param_symbol_id);
-                            
-                            // Add to symbol table
-                            symbol_table.push(Symbol {
-                                id: param_symbol_id,
-                                name: param_name.clone(),
-                                kind: SymbolKind::Variable {
-                                    data_type: func_decl.params.iter()
-                                        .find(|(name, _)| name == param_name)
-                                        .map(|(_, dt)| dt.clone())
-                                        .unwrap_or(DataType::String),
-                                },
-                            });
-                        }
-                        
-                        let body_start = instructions.len() as u32;
-                        for body_stmt in &func_decl.body {
-                            statement_to_instructions(body_stmt, &mut instructions, &func_symbol_map);
-                        }
-                        let body_end = instructions.len() as u32;
-                        let param_count = func_decl.params.len() as u32;
-                        instructions.push(Instruction::DeclareFunc { 
-                            symbol_id, 
-                            param_count, 
-                            param_symbol_ids: param_symbol_ids.clone(),
-                            body_start, 
-                            body_end 
-                        });
+                    None => {
+                        self.emit(Instruction::InitVar { symbol_id, value: Value::Null });
                     }
-                    Statement::SystemExec(func_call) => {
-                        let symbol_id = symbol_map[&func_call.name];
-                        let arg_count = func_call.args.len() as u32;
-                        for (_, arg_expr) in &func_call.args {
-                            expression_to_instructions(arg_expr, &mut instructions, &symbol_map);
+                }
+            }
+            StatementKind::SystemSet(var_assign) => {
+                let Some(symbol_id) = self.symbol(&var_assign.name, scope) else {
+                    return;
+                };
+                // Evaluate expression and leave result on stack
+                self.expression(&var_assign.value, scope);
+                self.emit(Instruction::SetVarFromStack { symbol_id });
+            }
+            StatementKind::SystemLog(log) => {
+                let log_type = log.log_type.parse().unwrap_or(LogType::Info);
+                let mut fields = Vec::new();
+                for argument in &log.arguments {
+                    self.expression(argument, scope);
+                    // `x.value` is reported as `x`, `x.type` keeps its suffix
+                    let key = match &argument.kind {
+                        ExpressionKind::Variable(name) => name.strip_suffix(".value").unwrap_or(name).to_string(),
+                        _ => fields.len().to_string(),
+                    };
+                    fields.push(key);
+                }
+                let expr_start = self.position();
+                self.expression(&log.message, scope);
+                let expr_end = self.position();
+                self.emit(Instruction::Log {
+                    log_type,
+                    fields,
+                    message_expr_start: expr_start,
+                    message_expr_end: expr_end,
+                });
+            }
+            StatementKind::FunctionDeclaration(func_decl) => {
+                // Semantic analysis rejects nested declarations, so every function was declared in the first pass
+                let Some(symbol_id) = self.symbol(&func_decl.name, scope) else {
+                    return;
+                };
+                
+                // Create symbol IDs for function parameters (they need their own scope)
+                let mut func_scope = scope.clone();
+                let param_symbol_ids = func_decl.params.iter()
+                    .map(|(param_name, param_type)| self.declare_variable(param_name, param_type, &mut func_scope))
+                    .collect();
+                
+                // The declaration precedes its body so the VM can jump over it
+                let declare_index = self.instructions.len();
+                self.emit(Instruction::DeclareFunc {
+                    symbol_id,
+                    param_count: func_decl.params.len() as u32,
+                    param_symbol_ids,
+                    body_start: declare_index as u32 + 1,
+                    body_end: 0, // Patched once the body is emitted
+                });
+                self.block(&func_decl.body, &func_scope);
+                // Implicit `return null;` when the body falls through
+                self.emit(Instruction::LoadValue { value: Value::Null });
+                self.emit(Instruction::Return);
+                let body_end = self.position();
+                if let Instruction::DeclareFunc { body_end: end, .. } = &mut self.instructions[declare_index] {
+                    *end = body_end;
+                }
+            }
+            StatementKind::SystemExec(func_call) => {
+                let Some(symbol_id) = self.symbol(&func_call.name, scope) else {
+                    return;
+                };
+                // Arguments are named, so push them in the order of the parameters they bind to
+                let params = self.params.get(&func_call.name).cloned().unwrap_or_default();
+                for param in &params {
+                    match func_call.args.iter().find(|(arg_name, _)| arg_name == param) {
+                        Some((_, arg_expr)) => self.expression(arg_expr, scope),
+                        None => {
+                            self.internal_error(format!("no argument for parameter '{}' of '{}'", param, func_call.name));
+                            return;
                         }
-                        instructions.push(Instruction::CallFunc { symbol_id, arg_count });
-                    }
-                    Statement::Return(expr) => {
-                        expression_to_instructions(expr, &mut instructions, &symbol_map);
-                        instructions.push(Instruction::Return);
                     }
-                    Statement::SystemInclude => {
-                        // Placeholder
+                }
+                let arg_count = params.len() as u32;
+                if func_call.spawn {
+                    self.emit(Instruction::SpawnTask { symbol_id, arg_count });
+                    match func_call.handle.as_ref().map(|handle| self.symbol(handle, scope)) {
+                        Some(Some(handle_id)) => self.emit(Instruction::SetVarFromStack { symbol_id: handle_id }),
+                        Some(None) => {}
+                        None => self.emit(Instruction::Pop),
                     }
+                } else {
+                    self.emit(Instruction::CallFunc { symbol_id, arg_count });
                 }
             }
+            StatementKind::SystemInput(input) => {
+                let Some(symbol_id) = self.symbol(&input.name, scope) else {
+                    return;
+                };
+                match &input.prompt {
+                    Some(prompt) => self.expression(prompt, scope),
+                    None => self.emit(Instruction::LoadValue { value: Value::String(String::new()) }),
+                }
+                self.emit(Instruction::CallBuiltin { builtin: Builtin::Input, arg_count: 1 });
+                self.emit(Instruction::SetVarFromStack { symbol_id });
+            }
+            StatementKind::SystemExit(exit) => {
+                match &exit.code {
+                    Some(code) => self.expression(code, scope),
+                    None => self.emit(Instruction::LoadValue { value: Value::Int(0) }),
+                }
+                self.emit(Instruction::Exit);
+            }
+            StatementKind::Try(try_catch) => {
+                let mut catch_scope = scope.clone();
+                let error_symbol_id = self.declare_variable(&try_catch.error_name, &DataType::String, &mut catch_scope);
+                
+                let enter_index = self.instructions.len();
+                self.emit(Instruction::EnterTry { catch_start: 0, error_symbol_id }); // Patched below
+                self.block(&try_catch.body, scope);
+                let exit_index = self.instructions.len();
+                self.emit(Instruction::ExitTry { catch_end: 0 }); // Patched below
+                
+                let catch_start = self.position();
+                self.block(&try_catch.handler, &catch_scope);
+                let catch_end = self.position();
+                
+                if let Instruction::EnterTry { catch_start: start, .. } = &mut self.instructions[enter_index] {
+                    *start = catch_start;
+                }
+                if let Instruction::ExitTry { catch_end: end } = &mut self.instructions[exit_index] {
+                    *end = catch_end;
+                }
+            }
+            StatementKind::Return(expr) => {
+                self.expression(expr, scope);
+                self.emit(Instruction::Return);
+            }
+            StatementKind::Expression(expr) => {
+                self.expression(expr, scope);
+                self.emit(Instruction::Pop);
+            }
+            StatementKind::SystemInclude => {
+                // Placeholder
+            }
         }
     }
-    
-    Program {
-        instructions,
-        string_table,
-        symbol_table,
-    }
-}
 
-fn expression_to_value(expr: &Expression) -> Value {
-    match expr {
-        Expression::Value(val) => val.clone(),
-        Expression::Variable(_) => Value::Null, // Can't resolve at compile time
-        Expression::Concat(_, _) => Value::Null, // Can't resolve at compile time
+    fn expression(&mut self, expr: &Expression, scope: &Scope) {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        self.expression_kind(&expr.kind, scope);
+        self.span = outer;
     }
-}
 
-fn expression_to_instructions(
-    expr: &Expression,
-    instructions: &mut Vec<Instruction>,
-    symbol_map: &std::collections::HashMap<String, u32>,
-) {
-    match expr {
-        Expression::Value(val) => {
-            instructions.push(Instruction::LoadValue { value: val.clone() });
-        }
-        Expression::Variable(var_name) => {
-            let parts: Vec<&str> = var_name.split('.').collect();
-            let name = parts[0];
-            if let Some(&symbol_id) = symbol_map.get(name) {
-                instructions.push(Instruction::LoadVar { symbol_id });
-            } else {
-                // Variable not found - push null as fallback
-                instructions.push(Instruction::LoadValue { value: Value::Null });
+    fn expression_kind(&mut self, kind: &ExpressionKind, scope: &Scope) {
+        match kind {
+            ExpressionKind::Value(val) => {
+                self.emit(Instruction::LoadValue { value: val.clone() });
+            }
+            ExpressionKind::Variable(var_name) => {
+                let (name, field) = var_name.split_once('.').unwrap_or((var_name, "value"));
+                let Some(symbol_id) = self.symbol(name, scope) else {
+                    return;
+                };
+                match (field, self.variable_type(symbol_id)) {
+                    // The declared type is known at compile time
+                    ("type", Some(data_type)) => {
+                        let value = Value::String(data_type.to_string());
+                        self.emit(Instruction::LoadValue { value });
+                    }
+                    _ => self.emit(Instruction::LoadVar { symbol_id }),
+                }
+            }
+            ExpressionKind::Concat(left, right) => {
+                self.expression(left, scope);
+                self.expression(right, scope);
+                self.emit(Instruction::Concat);
+            }
+            ExpressionKind::Arithmetic(op, left, right) => {
+                self.expression(left, scope);
+                self.expression(right, scope);
+                self.emit(Instruction::Arithmetic { op: *op });
+            }
+            ExpressionKind::Coalesce(left, right) => {
+                // The fallback is only evaluated when the left side is null
+                self.expression(left, scope);
+                let jump_index = self.instructions.len();
+                self.emit(Instruction::JumpIfNotNull { target: 0 }); // Patched below
+                self.expression(right, scope);
+                let end = self.position();
+                if let Instruction::JumpIfNotNull { target } = &mut self.instructions[jump_index] {
+                    *target = end;
+                }
+            }
+            ExpressionKind::Call(name, args) => {
+                for arg in args {
+                    self.expression(arg, scope);
+                }
+                match Builtin::from_name(name) {
+                    Some(builtin) => self.emit(Instruction::CallBuiltin { builtin, arg_count: args.len() as u32 }),
+                    None => self.internal_error(format!("'{}' is not a builtin", name)),
+                }
             }
-        }
-        Expression::Concat(left, right) => {
-            expression_to_instructions(left, instructions, symbol_map);
-            expression_to_instructions(right, instructions, symbol_map);
-            instructions.push(Instruction::Concat);
-        }
-    }
-}
-
-fn statement_to_instructions(
-    stmt: &Statement,
-    instructions: &mut Vec<Instruction>,
-    symbol_map: &std::collections::HashMap<String, u32>,
-) {
-    match stmt {
-        Statement::SystemInit(var_decl) => {
-            let symbol_id = symbol_map[&var_decl.name];
-            let value = var_decl.value.as_ref()
-                .map(|expr| expression_to_value(expr))
-                .unwrap_or(Value::Null);
-            instructions.push(Instruction::InitVar { symbol_id, value });
-        }
-        Statement::SystemSet(var_assign) => {
-            let symbol_id = symbol_map[&var_assign.name];
-            // Evaluate expression and leave result on stack
-            expression_to_instructions(&var_assign.value, instructions, symbol_map);
-            instructions.push(Instruction::SetVarFromStack { symbol_id });
-        }
-        Statement::SystemLog(log) => {
-            let log_type = match log.log_type.to_lowercase().as_str() {
-                "info" => LogType::Info,
-                "warn" => LogType::Warn,
-                "error" => LogType::Error,
-                _ => LogType::Info,
-            };
-            let expr_start = instructions.len() as u32;
-            expression_to_instructions(&log.message, instructions, symbol_map);
-            let expr_end = instructions.len() as u32;
-            instructions.push(Instruction::Log { log_type, message_expr_start: expr_start, message_expr_end: expr_end });
-        }
-        Statement::Return(expr) => {
-            expression_to_instructions(expr, instructions, symbol_map);
-            instructions.push(Instruction::Return);
-        }
-        _ => {
-            // Other statements handled at program level
         }
     }
 }
-
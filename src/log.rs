@@ -0,0 +1,407 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use colored::*;
+use serde::Deserialize;
+use crate::ast::Value;
+use crate::builtins::{json, time};
+use crate::ir::LogType;
+use crate::vm::value_to_string;
+
+/// How `system.log` messages are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[info] message`, coloured, for people reading a terminal
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs, one message per line
+    Logfmt,
+}
+
+impl LogFormat {
+    pub const ALL: &'static [LogFormat] = &[LogFormat::Text, LogFormat::Json, LogFormat::Logfmt];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+            LogFormat::Logfmt => "logfmt",
+        }
+    }
+
+    /// Render a record as a single line, without the trailing newline.
+    /// `color` only affects the text format, and so does `fields`: the
+    /// structured formats always include the record's fields.
+    pub fn format(self, record: &LogRecord, color: bool, fields: bool) -> String {
+        match self {
+            LogFormat::Text => format_text(record, color, fields),
+            LogFormat::Json => format_json(record),
+            LogFormat::Logfmt => format_logfmt(record),
+        }
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        LogFormat::ALL.iter().copied().find(|format| format.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = LogFormat::ALL.iter().map(|format| format.name()).collect();
+            format!("unknown log format '{}', expected one of {}", s, names.join(", "))
+        })
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A `system.log` message together with where and when it was logged
+#[derive(Debug, Clone)]
+pub struct LogRecord<'a> {
+    pub level: LogType,
+    pub message: String,
+    /// Values from the `arguments { ... }` block, keyed by variable name
+    pub fields: Vec<(String, Value)>,
+    pub source_file: &'a str,
+    pub line: u32,
+    /// Unix timestamp in seconds
+    pub timestamp: f64,
+}
+
+/// `[level] message`, followed by `name=value` for each field when `fields` is set
+fn format_text(record: &LogRecord, color: bool, fields: bool) -> String {
+    let fields = if fields { record.fields.as_slice() } else { &[] };
+    if !color {
+        let mut line = format!("[{}] {}", record.level, record.message);
+        for (key, value) in fields {
+            line.push_str(&format!(" {}={}", key, value_to_string(value)));
+        }
+        return line;
+    }
+    let level = match record.level {
+        LogType::Trace => "trace".dimmed(),
+        LogType::Debug => "debug".cyan().bold(),
+        LogType::Info => "info".blue().bold(),
+        LogType::Warn => "warn".yellow().bold(),
+        LogType::Error => "error".red().bold(),
+        LogType::Fatal => "fatal".on_red().bold(),
+    };
+    let mut line = format!("[{}] {}", level, record.message);
+    for (key, value) in fields {
+        line.push_str(&format!(" {}", format!("{}={}", key, value_to_string(value)).dimmed()));
+    }
+    line
+}
+
+/// Destination for log records; the VM hands every record that passes `--log-level` to each sink
+pub trait LogSink {
+    fn log(&mut self, record: &LogRecord) -> io::Result<()>;
+}
+
+/// Where a configured sink writes
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkTarget {
+    Stdout,
+    Stderr,
+    /// Appended to, creating the file and its directories if needed
+    File(PathBuf),
+}
+
+/// A sink as written on the command line or in `quentin.json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkConfig {
+    pub target: SinkTarget,
+    /// Least severe level written to this sink
+    #[serde(default)]
+    pub level: Option<LogType>,
+    /// Most severe level written to this sink
+    #[serde(default)]
+    pub max_level: Option<LogType>,
+    /// Defaults to the format given by `--log-format`
+    #[serde(default)]
+    pub format: Option<LogFormat>,
+}
+
+impl SinkConfig {
+    pub fn new(target: SinkTarget) -> Self {
+        Self { target, level: None, max_level: None, format: None }
+    }
+
+    /// `text_fields` appends the record's fields to text lines
+    pub fn open(&self, default_format: LogFormat, text_fields: bool) -> io::Result<Box<dyn LogSink>> {
+        let (writer, color): (Box<dyn Write>, bool) = match &self.target {
+            SinkTarget::Stdout => (Box::new(io::stdout()), true),
+            SinkTarget::Stderr => (Box::new(io::stderr()), true),
+            SinkTarget::File(path) => {
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                (Box::new(file), false)
+            }
+        };
+        Ok(Box::new(self.writer_sink(writer, color, default_format, text_fields)))
+    }
+
+    fn writer_sink(&self, writer: Box<dyn Write>, color: bool, default_format: LogFormat, text_fields: bool) -> WriterSink {
+        WriterSink {
+            writer,
+            color,
+            format: self.format.unwrap_or(default_format),
+            text_fields,
+            min_level: self.level.unwrap_or(LogType::Trace),
+            max_level: self.max_level.unwrap_or(LogType::Fatal),
+        }
+    }
+}
+
+/// Every level to stdout as coloured text, used when no sinks are configured
+pub fn stdout_sink() -> Box<dyn LogSink> {
+    Box::new(WriterSink {
+        writer: Box::new(io::stdout()),
+        color: true,
+        format: LogFormat::Text,
+        text_fields: false,
+        min_level: LogType::Trace,
+        max_level: LogType::Fatal,
+    })
+}
+
+/// Parses `TARGET[,level=LEVEL][,max_level=LEVEL][,format=FORMAT]`,
+/// where `TARGET` is `stdout`, `stderr` or `file=PATH`
+impl std::str::FromStr for SinkConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let target = match parts.next().unwrap_or_default().trim() {
+            "stdout" => SinkTarget::Stdout,
+            "stderr" => SinkTarget::Stderr,
+            other => match other.strip_prefix("file=") {
+                Some(path) if !path.is_empty() => SinkTarget::File(PathBuf::from(path)),
+                _ => return Err(format!("unknown log sink '{}', expected stdout, stderr or file=PATH", other)),
+            },
+        };
+        let mut config = SinkConfig::new(target);
+        for option in parts {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value in log sink option '{}'", option))?;
+            match key.trim() {
+                "level" => config.level = Some(value.parse()?),
+                "max_level" => config.max_level = Some(value.parse()?),
+                "format" => config.format = Some(value.parse()?),
+                other => return Err(format!("unknown log sink option '{}', expected level, max_level or format", other)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Formats records and writes them, one per line, to a stream or file
+struct WriterSink {
+    writer: Box<dyn Write>,
+    color: bool,
+    format: LogFormat,
+    text_fields: bool,
+    min_level: LogType,
+    max_level: LogType,
+}
+
+impl LogSink for WriterSink {
+    fn log(&mut self, record: &LogRecord) -> io::Result<()> {
+        if record.level < self.min_level || record.level > self.max_level {
+            return Ok(());
+        }
+        writeln!(self.writer, "{}", self.format.format(record, self.color, self.text_fields))?;
+        self.writer.flush()
+    }
+}
+
+fn format_json(record: &LogRecord) -> String {
+    let fields: serde_json::Map<String, serde_json::Value> = record.fields.iter()
+        .map(|(key, value)| {
+            // Numbers JSON cannot represent (NaN, infinity) fall back to their text
+            let value = json::to_json(value).unwrap_or_else(|_| serde_json::Value::String(value_to_string(value)));
+            (key.clone(), value)
+        })
+        .collect();
+    serde_json::json!({
+        "timestamp": timestamp(record),
+        "level": record.level.name(),
+        "message": record.message,
+        "fields": fields,
+        "source": { "file": record.source_file, "line": record.line },
+    })
+    .to_string()
+}
+
+fn format_logfmt(record: &LogRecord) -> String {
+    let mut pairs = vec![
+        ("time".to_string(), timestamp(record)),
+        ("level".to_string(), record.level.name().to_string()),
+        ("msg".to_string(), record.message.clone()),
+        ("file".to_string(), record.source_file.to_string()),
+        ("line".to_string(), record.line.to_string()),
+    ];
+    pairs.extend(record.fields.iter().map(|(key, value)| (key.clone(), value_to_string(value))));
+    let pairs: Vec<String> = pairs.iter().map(|(key, value)| format!("{}={}", key, logfmt_value(value))).collect();
+    pairs.join(" ")
+}
+
+/// Quote values that would otherwise be split or misread by logfmt parsers
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty() || value.chars().any(|c| c.is_whitespace() || c == '=' || c == '"');
+    if needs_quotes {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn timestamp(record: &LogRecord) -> String {
+    time::iso8601(record.timestamp).unwrap_or_else(|| record.timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn record(message: &str, fields: Vec<(&str, Value)>) -> LogRecord<'static> {
+        LogRecord {
+            level: LogType::Warn,
+            message: message.to_string(),
+            fields: fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+            source_file: "app.q",
+            line: 12,
+            timestamp: 1_709_209_815.25,
+        }
+    }
+
+    #[test]
+    fn json_is_one_object_per_line() {
+        let record = record("say \"hi\"\nbye", vec![
+            ("user", Value::String("ana".into())),
+            ("count", Value::Int(3)),
+            ("ratio", Value::Number(f64::NAN)),
+            ("tags", Value::Array(vec![Value::Null, Value::Bool(true)])),
+        ]);
+        let line = LogFormat::Json.format(&record, true, false);
+        assert!(!line.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed, serde_json::json!({
+            "timestamp": "2024-02-29T12:30:15.250Z",
+            "level": "warn",
+            "message": "say \"hi\"\nbye",
+            "fields": { "user": "ana", "count": 3, "ratio": "NaN", "tags": [null, true] },
+            "source": { "file": "app.q", "line": 12 },
+        }));
+    }
+
+    #[test]
+    fn logfmt_quotes_and_escapes_values() {
+        let record = record("say \"hi\"\nbye", vec![
+            ("user", Value::String("ana".into())),
+            ("empty", Value::String(String::new())),
+            ("expr", Value::String("a=b".into())),
+            ("path", Value::String("C:\\tmp".into())),
+            ("quoted", Value::String("\\\"".into())),
+            ("name", Value::String("héllo".into())),
+        ]);
+        assert_eq!(
+            LogFormat::Logfmt.format(&record, true, false),
+            r#"time=2024-02-29T12:30:15.250Z level=warn msg="say \"hi\"\nbye" file=app.q line=12 user=ana empty="" expr="a=b" path=C:\tmp quoted="\\\"" name=héllo"#
+        );
+    }
+
+    #[test]
+    fn text_shows_fields_only_when_asked() {
+        let record = record("signed in", vec![("user", Value::String("ana".into()))]);
+        assert_eq!(LogFormat::Text.format(&record, false, false), "[warn] signed in");
+        assert_eq!(LogFormat::Text.format(&record, false, true), "[warn] signed in user=ana");
+    }
+
+    #[test]
+    fn sink_options_parse() {
+        assert_eq!("stderr".parse::<SinkConfig>().unwrap(), SinkConfig::new(SinkTarget::Stderr));
+        let config: SinkConfig = "file=logs/app.log,level=debug,max_level=error,format=json".parse().unwrap();
+        assert_eq!(config, SinkConfig {
+            target: SinkTarget::File(PathBuf::from("logs/app.log")),
+            level: Some(LogType::Debug),
+            max_level: Some(LogType::Error),
+            format: Some(LogFormat::Json),
+        });
+        let errors = [
+            ("syslog", "unknown log sink 'syslog', expected stdout, stderr or file=PATH"),
+            ("file=", "unknown log sink 'file=', expected stdout, stderr or file=PATH"),
+            ("stdout,level", "expected key=value in log sink option 'level'"),
+            ("stdout,color=red", "unknown log sink option 'color', expected level, max_level or format"),
+            ("stdout,level=loud", "unknown log level 'loud', expected one of trace, debug, info, warn, error, fatal"),
+            ("stdout,format=xml", "unknown log format 'xml', expected one of text, json, logfmt"),
+        ];
+        for (text, message) in errors {
+            assert_eq!(text.parse::<SinkConfig>().unwrap_err(), message, "{}", text);
+        }
+    }
+
+    /// Collects what a sink writes
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sinks_receive_only_their_levels() {
+        let configs = ["stdout,max_level=info", "stderr,level=warn,format=logfmt"];
+        let buffers = [Buffer::default(), Buffer::default()];
+        let mut sinks: Vec<WriterSink> = configs.iter().zip(&buffers)
+            .map(|(config, buffer)| config.parse::<SinkConfig>().unwrap().writer_sink(Box::new(buffer.clone()), false, LogFormat::Text, false))
+            .collect();
+        for level in LogType::ALL {
+            let record = LogRecord { level: *level, ..record(level.name(), Vec::new()) };
+            for sink in &mut sinks {
+                sink.log(&record).unwrap();
+            }
+        }
+        let output = |buffer: &Buffer| String::from_utf8(buffer.0.take()).unwrap();
+        assert_eq!(output(&buffers[0]), "[trace] trace\n[debug] debug\n[info] info\n");
+        let lines: Vec<String> = output(&buffers[1]).lines().map(|line| line.split_once(' ').unwrap().1.to_string()).collect();
+        assert_eq!(lines, [
+            "level=warn msg=warn file=app.q line=12",
+            "level=error msg=error file=app.q line=12",
+            "level=fatal msg=fatal file=app.q line=12",
+        ]);
+    }
+
+    #[test]
+    fn file_sinks_create_directories_and_append() {
+        let dir = std::env::temp_dir().join(format!("quentin-log-{}", std::process::id()));
+        let path = dir.join("nested").join("app.log");
+        let config = SinkConfig::new(SinkTarget::File(path.clone()));
+        for message in ["first", "second"] {
+            config.open(LogFormat::Text, false).unwrap().log(&record(message, Vec::new())).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[warn] first\n[warn] second\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,35 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::{Parser as ClapParser, Subcommand};
 
 mod ast;
+mod builtins;
 mod parser;
 mod semantic;
 mod ir;
 mod codegen;
 mod build;
+mod config;
+mod diagnostic;
+mod cst;
+mod fmt;
+mod log;
+// Used by code generators that build an AST; nothing in the CLI calls it
+#[cfg_attr(not(test), allow(dead_code))]
+mod unparse;
 mod vm;
 
+use diagnostic::{Diagnostic, Source};
 use parser::*;
 use semantic::*;
 use ir::*;
 use codegen::*;
 use build::*;
 use vm::*;
+use builtins::fs::Permissions;
+use config::ProjectConfig;
+use log::{LogFormat, SinkConfig, SinkTarget};
 
 #[derive(ClapParser)]
 #[command(name = "quentin")]
@@ -26,6 +39,9 @@ use vm::*;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Print errors, warnings and logs without colour (also disabled by NO_COLOR)
+    #[arg(long = "no-color", global = true)]
+    no_color: bool,
 }
 
 #[derive(Subcommand)]
@@ -36,11 +52,50 @@ enum Commands {
         file: String,
         #[arg(long)]
         log: bool,
+        /// Also write these stages to the package directory, comma-separated:
+        /// tokens, ast (JSON), ir (JSON) or bytecode (a listing)
+        #[arg(long, value_name = "STAGES", value_delimiter = ',')]
+        emit: Vec<EmitStage>,
     },
     /// Run a built Q file
     Run {
         /// The path to the Q file to run
         file: String,
+        /// Seed for the random number generator, for reproducible runs
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Allow scripts to read files under this path (repeatable)
+        #[arg(long = "allow-read", value_name = "PATH")]
+        allow_read: Vec<PathBuf>,
+        /// Allow scripts to write and delete files under this path (repeatable)
+        #[arg(long = "allow-write", value_name = "PATH")]
+        allow_write: Vec<PathBuf>,
+        /// Hide log messages below this level: trace, debug, info, warn, error or fatal [default: info]
+        #[arg(long = "log-level", value_name = "LEVEL", env = "QUENTIN_LOG_LEVEL")]
+        log_level: Option<LogType>,
+        /// How log messages are written: text, json (one object per line) or logfmt [default: text]
+        #[arg(long = "log-format", value_name = "FORMAT", env = "QUENTIN_LOG_FORMAT")]
+        log_format: Option<LogFormat>,
+        /// Where log messages go (repeatable): stdout, stderr or file=PATH,
+        /// optionally followed by ,level=LEVEL ,max_level=LEVEL or ,format=FORMAT
+        #[arg(long = "log-sink", value_name = "SINK")]
+        log_sinks: Vec<SinkConfig>,
+        /// Append the values from `arguments { ... }` to text log lines as name=value;
+        /// json and logfmt always include them
+        #[arg(long = "log-fields")]
+        log_fields: bool,
+        /// Arguments passed to the script, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Rewrite Q files in the canonical style
+    Fmt {
+        /// The Q files to format
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Only report files that would change, exiting with 1 if there are any
+        #[arg(long)]
+        check: bool,
     },
     /// Clear the build cache
     Clear {
@@ -51,13 +106,45 @@ enum Commands {
 
 fn main() {
     let cli = Cli::parse();
+    if cli.no_color {
+        colored::control::set_override(false);
+    }
 
     match &cli.command {
-        Commands::Build { file, log } => {
-            compile_file(file, *log);
+        Commands::Build { file, log, emit } => {
+            compile_file(file, *log, emit);
+        }
+        Commands::Run { file, seed, allow_read, allow_write, log_level, log_format, log_sinks, log_fields, args } => {
+            let config = ProjectConfig::load().unwrap_or_else(|err| {
+                report(&err.diagnostic, config::CONFIG_FILE, Some(&err.source));
+                std::process::exit(1);
+            });
+            let mut permissions = Permissions::default();
+            for path in allow_read {
+                permissions.allow_read(path);
+            }
+            for path in allow_write {
+                permissions.allow_write(path);
+            }
+            let options = RunOptions {
+                seed: *seed,
+                permissions,
+                log_level: log_level.or(config.log.level).unwrap_or(LogType::Info),
+                log_format: log_format.or(config.log.format).unwrap_or_default(),
+                log_sinks: if log_sinks.is_empty() { config.log.sinks } else { log_sinks.clone() },
+                log_fields: *log_fields || config.log.fields,
+                args: args.clone(),
+            };
+            run_file(file, options);
         }
-        Commands::Run { file } => {
-            run_file(file);
+        Commands::Fmt { files, check } => {
+            let mut failed = false;
+            for file in files {
+                failed |= !format_file(file, *check);
+            }
+            if failed {
+                std::process::exit(1);
+            }
         }
         Commands::Clear { name } => {
             if let Some(name) = name {
@@ -74,7 +161,7 @@ fn main() {
 }
 
 /// Compilation pipeline: source -> parse -> AST -> semantic -> IR -> bytecode
-fn compile_file(source_file: &str, _log: bool) {
+fn compile_file(source_file: &str, _log: bool, emit: &[EmitStage]) {
     println!("Building file: {}", source_file);
     let input_path = Path::new(source_file);
     
@@ -82,21 +169,59 @@ fn compile_file(source_file: &str, _log: bool) {
     let source = fs::read_to_string(source_file)
         .expect("Should have been able to read the file");
     
-    let parse_tree = parse_source(&source)
-        .expect("Failed to parse source");
+    let source_map = Source::new(source);
+    
+    // Stage 2: AST Construction, continuing past syntax errors to report them all
+    let (ast, parse_errors) = parse_program(&source_map, 0);
+    if !parse_errors.is_empty() {
+        for diagnostic in &parse_errors {
+            report(diagnostic, source_file, Some(&source_map));
+        }
+        std::process::exit(1);
+    }
     
-    // Stage 2: AST Construction
-    let ast = build_ast(parse_tree);
+    // Stages asked for with --emit are written as soon as they exist, so
+    // they are there to inspect when a later stage fails
+    let package = PackageBuilder::new(input_path);
+    if !emit.is_empty() {
+        package.create()
+            .expect("Failed to create package directory");
+        for stage in emit {
+            // Leave no listing from an earlier build that this one does not reach
+            let _ = fs::remove_file(package.emit_path(*stage));
+        }
+    }
+    write_stage(&package, emit, EmitStage::Tokens, || {
+        let cst = cst::parse_cst(&source_map.text).expect("Source parsed without errors above");
+        cst::token_listing(&cst, &source_map)
+    });
+    write_stage(&package, emit, EmitStage::Ast, || {
+        serde_json::to_string_pretty(&ast).expect("Failed to serialize AST") + "\n"
+    });
     
     // Stage 3: Semantic Analysis
-    analyze(&ast)
-        .expect("Semantic analysis failed");
+    let warnings = analyze(&ast)
+        .unwrap_or_else(|err| {
+            report(&err.node.diagnostic(err.span), source_file, Some(&source_map));
+            std::process::exit(1);
+        });
+    for warning in &warnings {
+        report(&warning.node.diagnostic(warning.span), source_file, Some(&source_map));
+    }
     
     // Stage 4: IR Generation
-    let ir = ast_to_ir(&ast);
+    let ir = ast_to_ir(&ast, source_file)
+        .unwrap_or_else(|errors| {
+            for diagnostic in &errors {
+                report(diagnostic, source_file, Some(&source_map));
+            }
+            std::process::exit(1);
+        });
+    write_stage(&package, emit, EmitStage::Ir, || {
+        serde_json::to_string_pretty(&ir).expect("Failed to serialize IR") + "\n"
+    });
     
     // Stage 5: Binary Emission
-    let package = PackageBuilder::new(input_path);
     package.create()
         .expect("Failed to create package directory");
     
@@ -105,23 +230,166 @@ fn compile_file(source_file: &str, _log: bool) {
     
     package.write_manifest(bytecode_size)
         .expect("Failed to write manifest");
+    write_stage(&package, emit, EmitStage::Bytecode, || {
+        let program = load_bytecode(&package.bytecode_path()).expect("Failed to read back bytecode");
+        disassemble(&program)
+    });
     
     println!("Successfully built to {}", package.package_dir().display());
 }
 
+/// Write `stage` to the package directory if it is one of the `emit` stages
+fn write_stage(package: &PackageBuilder, emit: &[EmitStage], stage: EmitStage, contents: impl FnOnce() -> String) {
+    if !emit.contains(&stage) {
+        return;
+    }
+    let path = package.emit_path(stage);
+    fs::write(&path, contents()).expect("Failed to write emitted stage");
+    println!("Wrote {} to {}", stage.name(), path.display());
+}
+
+/// Print a diagnostic to stderr, quoting the source line when it is available
+fn report(diagnostic: &Diagnostic, source_file: &str, source: Option<&Source>) {
+    let color = colored::control::SHOULD_COLORIZE.should_colorize();
+    eprintln!("{}\n", diagnostic.render(source_file, source, color));
+}
+
+/// Format one file in place, or with `check` only report whether it would
+/// change. Returns false if the file is not formatted or does not parse.
+fn format_file(source_file: &str, check: bool) -> bool {
+    let source = fs::read_to_string(source_file)
+        .expect("Should have been able to read the file");
+    let formatted = match fmt::format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(diagnostics) => {
+            let source_map = Source::new(source);
+            for diagnostic in &diagnostics {
+                report(diagnostic, source_file, Some(&source_map));
+            }
+            return false;
+        }
+    };
+    if formatted == source {
+        return true;
+    }
+    if check {
+        println!("Would reformat: {}", source_file);
+        return false;
+    }
+    fs::write(source_file, formatted).expect("Failed to write formatted file");
+    println!("Formatted: {}", source_file);
+    true
+}
+
+/// Settings from the `run` command line that are handed to the VM
+struct RunOptions {
+    seed: Option<u64>,
+    permissions: Permissions,
+    log_level: LogType,
+    log_format: LogFormat,
+    /// Empty to log to stdout
+    log_sinks: Vec<SinkConfig>,
+    log_fields: bool,
+    args: Vec<String>,
+}
+
 /// Execution pipeline: load bytecode -> execute in VM
-fn run_file(source_file: &str) {
+fn run_file(source_file: &str, options: RunOptions) {
     let input_path = Path::new(source_file);
     let bytecode_path = load_package(input_path)
         .expect("Failed to load package");
     
-    println!("Running build: {}", bytecode_path.parent().unwrap().display());
+    let mut sink_configs = options.log_sinks;
+    if sink_configs.is_empty() {
+        sink_configs.push(SinkConfig::new(SinkTarget::Stdout));
+    }
+    // Structured logs on stdout are one record per line, with nothing in between
+    let structured_stdout = sink_configs.iter()
+        .any(|sink| sink.target == SinkTarget::Stdout && sink.format.unwrap_or(options.log_format) != LogFormat::Text);
+    if !structured_stdout {
+        println!("Running build: {}", bytecode_path.parent().unwrap().display());
+    }
     
     // Load bytecode
     let program = load_bytecode(&bytecode_path)
         .expect("Failed to load bytecode");
     
     // Execute in VM
+    let program_source = program.source_file.clone();
     let mut vm = VM::new(program);
-    vm.execute();
+    if let Some(seed) = options.seed {
+        vm.seed_random(seed);
+    }
+    vm.set_permissions(options.permissions);
+    vm.set_log_level(options.log_level);
+    let sinks = sink_configs.iter()
+        .map(|sink| sink.open(options.log_format, options.log_fields))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Failed to open log sink");
+    vm.set_log_sinks(sinks);
+    vm.set_prompt_to_stderr(structured_stdout);
+    vm.set_args(options.args);
+    let status = vm.execute();
+    if let ExitStatus::Failed(err, span) = &status {
+        // The source may have moved or changed since the build; the location is still reported
+        let source = fs::read_to_string(&program_source).ok().map(Source::new);
+        report(&err.diagnostic(*span), &program_source, source.as_ref());
+    }
+    std::process::exit(status.code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fmt_check_reports_without_writing() {
+        let dir = std::env::temp_dir().join(format!("quentin-fmt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.q");
+        let path = path.to_str().unwrap();
+        let unformatted = "let x:int=1;\nlog info   \"x\"; // note\n";
+
+        fs::write(path, unformatted).unwrap();
+        assert!(!format_file(path, true));
+        assert_eq!(fs::read_to_string(path).unwrap(), unformatted);
+
+        assert!(format_file(path, false));
+        let formatted = fs::read_to_string(path).unwrap();
+        assert_eq!(formatted, "let x: int = 1;\nlog info \"x\"; // note\n");
+        assert!(format_file(path, true));
+
+        fs::write(path, "let x: int = ;\n").unwrap();
+        assert!(!format_file(path, true));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn emit_takes_a_comma_separated_list_of_stages() {
+        let emit = |args: &[&str]| match Cli::try_parse_from(args).map(|cli| cli.command) {
+            Ok(Commands::Build { emit, .. }) => Ok(emit),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(err.to_string()),
+        };
+        assert_eq!(emit(&["quentin", "build", "app.q"]), Ok(vec![]));
+        assert_eq!(emit(&["quentin", "build", "app.q", "--emit", "tokens,ast"]), Ok(vec![EmitStage::Tokens, EmitStage::Ast]));
+        assert_eq!(emit(&["quentin", "build", "app.q", "--emit=IR", "--emit", "bytecode"]), Ok(vec![EmitStage::Ir, EmitStage::Bytecode]));
+        let err = emit(&["quentin", "build", "app.q", "--emit", "ast,cst"]).unwrap_err();
+        assert!(err.contains("unknown stage 'cst', expected one of tokens, ast, ir, bytecode"), "{}", err);
+    }
+
+    #[test]
+    fn log_level_flag_overrides_the_environment() {
+        let level = |args: &[&str]| match Cli::try_parse_from(args).unwrap().command {
+            Commands::Run { log_level, .. } => log_level,
+            _ => unreachable!(),
+        };
+        std::env::set_var("QUENTIN_LOG_LEVEL", "debug");
+        assert_eq!(level(&["quentin", "run", "app.q"]), Some(LogType::Debug));
+        assert_eq!(level(&["quentin", "run", "app.q", "--log-level", "error"]), Some(LogType::Error));
+        std::env::set_var("QUENTIN_LOG_LEVEL", "verbose");
+        assert!(Cli::try_parse_from(["quentin", "run", "app.q"]).is_err());
+        std::env::remove_var("QUENTIN_LOG_LEVEL");
+        assert_eq!(level(&["quentin", "run", "app.q"]), None);
+    }
 }
@@ -1,244 +1,873 @@
+use std::cell::RefCell;
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Source};
+use crate::ir::LogType;
 
 #[derive(Parser)]
 #[grammar = "q.pest"]
 pub struct QParser;
 
 /// Parse source code into pest parse tree
-pub fn parse_source(source: &str) -> Result<pest::iterators::Pairs<'_, Rule>, pest::error::Error<Rule>> {
-    QParser::parse(Rule::file, source)
+pub fn parse_source(source: &str) -> Result<pest::iterators::Pairs<'_, Rule>, Box<pest::error::Error<Rule>>> {
+    QParser::parse(Rule::file, source).map_err(Box::new)
 }
 
-/// Build AST from parse tree
-pub fn build_ast(mut pairs: pest::iterators::Pairs<Rule>) -> Vec<AstNode> {
+/// Parse a whole file, recovering from syntax errors at statement boundaries.
+/// Returns the statements that could be built, which is the full AST when
+/// there are no errors, and a diagnostic for every error found.
+pub fn parse_program(source: &Source, file_id: u32) -> (Vec<AstNode>, Vec<Diagnostic>) {
+    if let Ok(pairs) = parse_source(&source.text) {
+        return build_ast(pairs, file_id);
+    }
+
+    // Record which tokens were expected, for the error messages
+    pest::set_error_detail(true);
+    let text = source.text.as_str();
+    let mut ast = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut pos = 0;
+    loop {
+        pos += text[pos..].len() - text[pos..].trim_start().len();
+        if pos == text.len() {
+            break;
+        }
+        match QParser::parse(Rule::item, &text[pos..]) {
+            Ok(mut pairs) => {
+                let pair = pairs.next().unwrap();
+                let end = pos + pair.as_span().end();
+                if pair.as_rule() == Rule::statement {
+                    let builder = AstBuilder::new(source, file_id, pos);
+                    ast.extend(builder.build_statement(pair).map(AstNode::Statement));
+                    diagnostics.extend(builder.diagnostics.into_inner());
+                }
+                pos = end;
+            }
+            Err(err) => {
+                let diagnostic = syntax_error(&err, source, file_id, pos);
+                let error_pos = diagnostic.span.map_or(pos, |span| span.start);
+                diagnostics.push(diagnostic);
+                pos = resync(text, pos, error_pos);
+            }
+        }
+    }
+    (ast, diagnostics)
+}
+
+/// Describe a pest error as a diagnostic pointing at where parsing stopped.
+/// `offset` is where the parsed input starts in `source`.
+fn syntax_error(err: &pest::error::Error<Rule>, source: &Source, file_id: u32, offset: usize) -> Diagnostic {
+    let Some(attempts) = err.parse_attempts() else {
+        let (start, end) = match err.location {
+            pest::error::InputLocation::Pos(pos) => (pos, pos),
+            pest::error::InputLocation::Span(span) => span,
+        };
+        let err = err.clone().renamed_rules(|rule| describe_rule(*rule).to_string());
+        return Diagnostic::error("E0001", format!("syntax error: {}", err.variant.message()))
+            .with_span(source.span(file_id, offset + start, offset + end));
+    };
+
+    let mut pos = offset + attempts.max_position;
+    let mut rules: Vec<Rule> = attempts.call_stacks().iter().filter_map(|stack| stack.deepest.get_rule().copied()).collect();
+    let mut tokens: Vec<String> = attempts.expected_tokens().iter()
+        .map(|token| token.to_string())
+        .filter(|token| !token.trim().is_empty())
+        .collect();
+    // A keyword that runs into a longer word, such as `int` in `integer`, fails
+    // inside the word; report the whole word where the keyword rule started
+    if let (pest::error::InputLocation::Pos(start), pest::error::ErrorVariant::ParsingError { positives, .. }) = (&err.location, &err.variant) {
+        let start = offset + start;
+        let keyword_rules: Vec<Rule> = positives.iter().copied().filter(|rule| KEYWORD_RULES.contains(rule)).collect();
+        if !keyword_rules.is_empty() && start < pos && pos <= start + word_at(&source.text, start).len() {
+            pos = start;
+            rules = keyword_rules;
+            tokens.clear();
+        }
+    }
+    let found = word_at(&source.text, pos);
+
+    // Keywords such as log types read better as the rule that lists them
+    let mut expected: Vec<String> = Vec::new();
+    let keyword_rules: Vec<Rule> = rules.iter().copied().filter(|rule| KEYWORD_RULES.contains(rule)).collect();
+    if !keyword_rules.is_empty() {
+        tokens.retain(|token| !token.chars().all(|c| c.is_ascii_alphabetic()));
+        expected.extend(keyword_rules.iter().map(|rule| describe_rule(*rule).to_string()));
+    }
+    if tokens.len() > MAX_LISTED_TOKENS || (tokens.is_empty() && expected.is_empty()) {
+        expected.extend(rules.iter().map(|rule| describe_rule(*rule).to_string()));
+    } else {
+        expected.extend(tokens.iter().map(|token| describe_token(token)));
+    }
+    expected.dedup();
+    let expected = if expected.is_empty() { describe_rule(Rule::statement).to_string() } else { join_or(&expected) };
+    let message = if found.is_empty() {
+        format!("syntax error: expected {}, found end of file", expected)
+    } else {
+        format!("syntax error: expected {}", expected)
+    };
+
+    let mut diagnostic = Diagnostic::error("E0001", message)
+        .with_span(source.span(file_id, pos, pos + found.len()));
+    for help in syntax_hints(&source.text[pos..], found, &tokens, &rules) {
+        diagnostic = diagnostic.with_help(help);
+    }
+    diagnostic
+}
+
+/// Rules whose alternatives are plain keywords, described as a whole in messages
+const KEYWORD_RULES: &[Rule] = &[Rule::log_type, Rule::datatype, Rule::variable_type, Rule::exec_type, Rule::boolean];
+
+/// Beyond this many expected tokens, messages name the rules instead
+const MAX_LISTED_TOKENS: usize = 8;
+
+/// Help for common mistakes at the position of a syntax error; `rest` is
+/// the source from there on and `found` the word it starts with
+fn syntax_hints(rest: &str, found: &str, tokens: &[String], rules: &[Rule]) -> Vec<String> {
+    let mut hints = Vec::new();
+    let is_word = found.starts_with(|c: char| c.is_ascii_alphabetic());
+    let quoted = format!("\"{}\"", found);
+    if is_word && tokens.contains(&quoted) {
+        hints.push(format!("keys are quoted: write {}: instead of {}:", quoted, found));
+    }
+    if rest.starts_with('=') && !rest.starts_with("=>") && tokens.iter().any(|token| token == ":") {
+        hints.push("use `:` between a key and its value, not `=`".to_string());
+    }
+    if rest.starts_with('"') && tokens.iter().any(|token| token == "a..z") {
+        let name = rest[1..].split('"').next().unwrap_or("");
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            hints.push(format!("remove the quotes around the name: write {} instead of \"{}\"", name, name));
+        } else {
+            hints.push("remove the quotes around the name".to_string());
+        }
+    }
+    if is_word && rules.contains(&Rule::log_type) {
+        let names: Vec<&str> = LogType::ALL.iter().map(|log_type| log_type.name()).collect();
+        hints.push(format!("`{}` is not a log type; use {}", found, join_or(&names)));
+    }
+    if is_word && rules.contains(&Rule::datatype) {
+        hints.push(format!("`{}` is not a type; use string, number, int, bool or any, optionally followed by [] or ?", found));
+    }
+    hints
+}
+
+/// The identifier or number at `pos`, else the single character there, or "" at the end of the input
+fn word_at(text: &str, pos: usize) -> &str {
+    let rest = &text[pos..];
+    let word_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    match word_len {
+        0 => rest.chars().next().map_or("", |c| &rest[..c.len_utf8()]),
+        len => &rest[..len],
+    }
+}
+
+fn describe_token(token: &str) -> String {
+    match token {
+        "}" | ")" => format!("a closing `{}`", token),
+        // The character ranges that start an identifier or a number
+        "A..Z" | "a..z" => "`<identifier>`".to_string(),
+        "0..9" => "a digit".to_string(),
+        _ => format!("`{}`", token),
+    }
+}
+
+/// `a`, `a or b`, `a, b or c`
+fn join_or<T: AsRef<str>>(items: &[T]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.as_ref().to_string(),
+        [init @ .., last] => {
+            let init: Vec<&str> = init.iter().map(AsRef::as_ref).collect();
+            format!("{} or {}", init.join(", "), last.as_ref())
+        }
+    }
+}
+
+/// How a rule is named in "expected ..." messages
+fn describe_rule(rule: Rule) -> &'static str {
+    match rule {
+        Rule::EOI => "end of file",
+        Rule::file => "a Q program",
+        Rule::statement | Rule::statements | Rule::item => "a statement",
+        Rule::WHITESPACE => "whitespace",
+        Rule::comment => "a comment",
+        Rule::system_include => "`system.include { ... }`",
+        Rule::include_block | Rule::from_import => "`from \"file.q\" import { ... }`",
+        Rule::import_list | Rule::import_item => "an import such as `\"name\": module::item`",
+        Rule::string => "a string such as `\"text\"`",
+        Rule::char => "a character",
+        Rule::system_init => "`system.init { ... }`",
+        Rule::init_pairs | Rule::init_pair => "`\"type\"`, `\"name\"`, `\"datatype\"` or `\"value\"`",
+        Rule::variable_type => "`variable` or `array`",
+        Rule::datatype => "a type (string, number, int, bool or any)",
+        Rule::value => "a value",
+        Rule::number => "a number",
+        Rule::boolean => "`true` or `false`",
+        Rule::null => "`null`",
+        Rule::identifier => "a name such as `my_var`",
+        Rule::system_set => "`system.set { ... }`",
+        Rule::set_pairs | Rule::set_pair => "`\"name\": <identifier>` or `\"value\": <expression>`",
+        Rule::system_log => "`system.log { ... }`",
+        Rule::log_pairs | Rule::log_pair => "`\"type\"`, `\"message\"` or `arguments { ... }`",
+        Rule::log_type => "a log type (trace, debug, info, warn, error or fatal)",
+        Rule::arguments | Rule::argument => "a variable reference such as `x.value` or `x.type`",
+        Rule::expression | Rule::coalesce | Rule::sum | Rule::product | Rule::term => "an expression",
+        Rule::add_op => "`+` or `-`",
+        Rule::mul_op => "`*`, `/` or `%`",
+        Rule::builtin_call => "a function call such as `length(s.value)`",
+        Rule::call_args => "function arguments",
+        Rule::system_input => "`system.input { ... }`",
+        Rule::input_pairs | Rule::input_pair => "`\"name\": <identifier>` or `\"prompt\": <expression>`",
+        Rule::system_exit => "`system.exit { ... }`",
+        Rule::exit_pairs | Rule::exit_pair => "`\"code\": <expression>`",
+        Rule::function_decl => "a function declaration",
+        Rule::params | Rule::param => "a parameter such as `name in string`",
+        Rule::system_exec => "`system.exec { ... }`",
+        Rule::exec_pairs | Rule::exec_pair => "`\"type\"`, `\"name\"`, `\"handle\"` or `parameters { ... }`",
+        Rule::exec_type => "`function` or `task`",
+        Rule::exec_handle => "a variable to store the task id in",
+        Rule::exec_params | Rule::exec_param => "a parameter such as `name => value`",
+        Rule::try_statement => "`try { ... } catch (err) { ... }`",
+        Rule::return_statement => "`return <expression>;`",
+        Rule::call_statement => "a function call statement",
+        Rule::let_statement => "`let name: type = value;`",
+        Rule::assign_statement => "an assignment such as `x = 1;`",
+        Rule::log_statement => "`log <type> <expression>;`",
+        Rule::call_function => "`call name(...);`",
+        Rule::let_keyword => "`let`",
+        Rule::return_keyword => "`return`",
+        Rule::log_keyword => "`log`",
+        Rule::call_keyword => "`call`",
+    }
+}
+
+/// Keywords that start a statement; a line beginning with one is taken as a
+/// new statement even when the braces before it are unbalanced
+const STATEMENT_KEYWORDS: &[&str] = &["system.", "function ", "try ", "try{", "let ", "log ", "call "];
+
+/// Find where parsing should resume after a syntax error at `error` in the
+/// statement starting at `start`: after the `;` or closing `}` that ends it,
+/// or at the next line that starts with a statement keyword, whichever comes first
+fn resync(text: &str, start: usize, error: usize) -> usize {
+    let bytes = text.as_bytes();
+    let error_line = line_start(text, error);
+    let mut depth = 0i32;
+    let mut i = start;
+    while i < bytes.len() {
+        let at_line_start = i > start && bytes[i - 1] == b'\n';
+        if at_line_start && i >= error_line && STATEMENT_KEYWORDS.iter().any(|k| text[i..].starts_with(k)) {
+            return i;
+        }
+        match bytes[i] {
+            b'"' => {
+                // Skip the string, honouring escapes; an unterminated string ends at the line
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i + 1 < bytes.len() && bytes[i + 1] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'{' | b'(' => depth += 1,
+            b')' => depth -= 1,
+            b'}' => {
+                depth -= 1;
+                if depth <= 0 && i >= error {
+                    let rest = text[i + 1..].trim_start();
+                    let after = text.len() - rest.len();
+                    if rest.starts_with(';') {
+                        return after + 1;
+                    }
+                    if !rest.starts_with("catch") {
+                        return i + 1;
+                    }
+                }
+            }
+            b';' if depth <= 0 && i >= error => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    text.len()
+}
+
+/// Byte offset at which the line containing `pos` starts
+fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map_or(0, |index| index + 1)
+}
+
+/// Build AST from parse tree; `file_id` is recorded in every span.
+/// Statements that parsed but are incomplete, such as a `system.init`
+/// without a name, are left out and reported.
+pub fn build_ast(mut pairs: pest::iterators::Pairs<Rule>, file_id: u32) -> (Vec<AstNode>, Vec<Diagnostic>) {
     let file = pairs.next().unwrap();
     if file.as_rule() != Rule::file {
-        return vec![];
+        return (vec![], vec![]);
     }
 
-    file.into_inner()
+    let source = Source::new(file.as_str());
+    let builder = AstBuilder::new(&source, file_id, 0);
+    let ast = file.into_inner()
         .filter_map(|pair| match pair.as_rule() {
-            Rule::statement => build_statement(pair).map(AstNode::Statement),
+            Rule::statement => builder.build_statement(pair).map(AstNode::Statement),
             Rule::EOI => None,
             Rule::comment => None,
-            _ => {
-                println!("unhandled rule: {:?}", pair.as_rule());
+            rule => {
+                builder.unexpected(rule, builder.span(pair.as_span()));
                 None
             }
         })
-        .collect()
-}
-
-fn build_statement(pair: Pair<Rule>) -> Option<Statement> {
-    let inner = pair.into_inner().next().unwrap();
-    match inner.as_rule() {
-        Rule::system_init => {
-            let mut name = None;
-            let mut data_type = None;
-            let mut value = None;
-            // Find init_pairs in the children
-            let mut inner_iter = inner.into_inner();
-            let init_pairs = inner_iter.find(|p| p.as_rule() == Rule::init_pairs);
-            if let Some(init_pairs) = init_pairs {
-                for part in init_pairs.into_inner() {
-                    if part.as_rule() == Rule::init_pair {
-                        // The init_pair only contains the value part (the key is consumed by the alternative match)
-                        // We determine which alternative matched by looking at the child's rule type
-                        let mut pair_inner = part.into_inner();
-                        if let Some(val_pair) = pair_inner.next() {
-                            match val_pair.as_rule() {
-                                Rule::variable_type => {
-                                    // This is the "type" alternative - ignore for now
-                                }
-                                Rule::identifier => {
-                                    // This is the "name" alternative
-                                    name = Some(val_pair.as_str().to_string());
-                                }
-                                Rule::datatype => {
-                                    data_type = Some(match val_pair.as_str() {
-                                        "string" => DataType::String,
-                                        "number" => DataType::Number,
-                                        "bool" => DataType::Bool,
-                                        _ => unreachable!(),
-                                    })
-                                }
-                                Rule::value => {
-                                    value = Some(build_expression(val_pair));
-                                }
-                                _ => {}
-                            }
+        .collect();
+    (ast, builder.diagnostics.into_inner())
+}
+
+/// The key of a `key: value` pair in a `system.*` block, as written: either
+/// quoted, like `"name"`, or bare, like `arguments`
+pub fn pair_key<'i>(pair: &Pair<'i, Rule>) -> &'i str {
+    let text = pair.as_str();
+    let key_len = match text.strip_prefix('"') {
+        Some(rest) => rest.find('"').map_or(text.len(), |end| end + 2),
+        None => text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len()),
+    };
+    &text[..key_len]
+}
+
+/// Turns parse tree pairs into AST nodes with source spans
+struct AstBuilder<'a> {
+    file_id: u32,
+    source: &'a Source,
+    /// Where the parsed input starts in `source`, when a single statement was parsed
+    offset: usize,
+    /// Problems in statements that parsed but cannot be built, such as a missing key
+    diagnostics: RefCell<Vec<Diagnostic>>,
+}
+
+impl<'a> AstBuilder<'a> {
+    fn new(source: &'a Source, file_id: u32, offset: usize) -> Self {
+        Self { file_id, source, offset, diagnostics: RefCell::new(Vec::new()) }
+    }
+
+    fn span(&self, span: pest::Span) -> Span {
+        // pest's own line_col scans from the start of the input, so look the line up instead
+        self.source.span(self.file_id, self.offset + span.start(), self.offset + span.end())
+    }
+
+    /// Build the statements of a `{ ... }` block, skipping comments
+    fn build_block(&self, pair: Pair<Rule>) -> Vec<Statement> {
+        pair.into_inner()
+            .filter(|p| p.as_rule() == Rule::statement)
+            .filter_map(|p| self.build_statement(p))
+            .collect()
+    }
+
+    fn build_statement(&self, pair: Pair<Rule>) -> Option<Statement> {
+        let inner = pair.into_inner().next().unwrap();
+        let span = self.span(inner.as_span());
+        let kind = match inner.as_rule() {
+            Rule::system_init => {
+                let mut name = None;
+                let mut data_type = None;
+                let mut value = None;
+                let mut is_array = false;
+                // Find init_pairs in the children
+                let mut inner_iter = inner.into_inner();
+                let init_pairs = inner_iter.find(|p| p.as_rule() == Rule::init_pairs);
+                // The key is consumed by the alternative match, so the value's rule tells which key was given
+                for val_pair in self.block_pairs("system.init", init_pairs) {
+                    match val_pair.as_rule() {
+                        Rule::variable_type => {
+                            is_array = val_pair.as_str() == "array";
+                        }
+                        Rule::identifier => {
+                            // This is the "name" alternative
+                            name = Some(val_pair.as_str().to_string());
+                        }
+                        Rule::datatype => {
+                            data_type = Some(build_data_type(val_pair))
+                        }
+                        Rule::expression => {
+                            value = Some(self.build_expression(val_pair));
                         }
+                        _ => {}
                     }
                 }
+                let name = self.require(name, "system.init", "\"name\": my_var", span);
+                let data_type = self.require(data_type, "system.init", "\"datatype\": int", span);
+                // "datatype" names the element type of an array
+                let data_type = data_type?;
+                let data_type = if is_array { array_of(data_type) } else { data_type };
+                Some(StatementKind::SystemInit(VariableDeclaration {
+                    name: name?,
+                    data_type,
+                    value,
+                }))
             }
-            Some(Statement::SystemInit(VariableDeclaration {
-                name: name.unwrap(),
-                data_type: data_type.unwrap(),
-                value,
-            }))
-        }
-        Rule::system_set => {
-            let mut name = None;
-            let mut value = None;
-            let mut inner_iter = inner.into_inner();
-            let set_pairs = inner_iter.find(|p| p.as_rule() == Rule::set_pairs);
-            if let Some(set_pairs) = set_pairs {
-                for part in set_pairs.into_inner() {
-                    if part.as_rule() == Rule::set_pair {
-                        let mut pair_inner = part.into_inner();
-                        if let Some(val_pair) = pair_inner.next() {
-                            match val_pair.as_rule() {
-                                Rule::identifier => {
-                                    name = Some(val_pair.as_str().to_string());
-                                }
-                                Rule::value => {
-                                    value = Some(build_expression(val_pair));
-                                }
-                                _ => {}
-                            }
+            Rule::system_set => {
+                let mut name = None;
+                let mut value = None;
+                let mut inner_iter = inner.into_inner();
+                let set_pairs = inner_iter.find(|p| p.as_rule() == Rule::set_pairs);
+                for val_pair in self.block_pairs("system.set", set_pairs) {
+                    match val_pair.as_rule() {
+                        Rule::identifier => {
+                            name = Some(val_pair.as_str().to_string());
+                        }
+                        Rule::expression => {
+                            value = Some(self.build_expression(val_pair));
                         }
+                        _ => {}
                     }
                 }
+                let name = self.require(name, "system.set", "\"name\": my_var", span);
+                let value = self.require(value, "system.set", "\"value\": 1", span);
+                Some(StatementKind::SystemSet(VariableAssignment {
+                    name: name?,
+                    value: value?,
+                }))
             }
-            Some(Statement::SystemSet(VariableAssignment {
-                name: name.unwrap(),
-                value: value.unwrap(),
-            }))
-        }
-        Rule::system_log => {
-            let mut log_type = None;
-            let mut message = None;
-            let mut inner_iter = inner.into_inner();
-            let log_pairs = inner_iter.find(|p| p.as_rule() == Rule::log_pairs);
-            if let Some(log_pairs) = log_pairs {
-                for part in log_pairs.into_inner() {
-                    if part.as_rule() == Rule::log_pair {
-                        let mut pair_inner = part.into_inner();
-                        if let Some(val_pair) = pair_inner.next() {
-                            match val_pair.as_rule() {
-                                Rule::log_type => {
-                                    log_type = Some(val_pair.as_str().to_string());
-                                }
-                                Rule::expression => {
-                                    message = Some(build_expression(val_pair));
-                                }
-                                Rule::arguments => {
-                                    // Ignore arguments for now
-                                }
-                                _ => {}
-                            }
+            Rule::system_log => {
+                let mut log_type = None;
+                let mut arguments = Vec::new();
+                let mut message = None;
+                let mut inner_iter = inner.into_inner();
+                let log_pairs = inner_iter.find(|p| p.as_rule() == Rule::log_pairs);
+                for val_pair in self.block_pairs("system.log", log_pairs) {
+                    match val_pair.as_rule() {
+                        Rule::log_type => {
+                            log_type = Some(val_pair.as_str().to_string());
+                        }
+                        Rule::expression => {
+                            message = Some(self.build_expression(val_pair));
                         }
+                        Rule::arguments => {
+                            arguments.extend(val_pair.into_inner().map(|arg| self.build_expression(arg)));
+                        }
+                        _ => {}
                     }
                 }
+                let log_type = self.require(log_type, "system.log", "\"type\": info", span);
+                let message = self.require(message, "system.log", "\"message\": \"Hello\"", span);
+                Some(StatementKind::SystemLog(Log {
+                    log_type: log_type?,
+                    arguments,
+                    message: message?,
+                }))
             }
-            Some(Statement::SystemLog(Log {
-                log_type: log_type.unwrap(),
-                message: message.unwrap(),
-            }))
-        }
-        Rule::function_decl => {
-            let mut inner_rules = inner.into_inner();
-            let name = inner_rules.next().unwrap().as_str().to_string();
-            let params_pair = inner_rules.next().unwrap();
-            let body_pair = inner_rules.next().unwrap();
-
-            let params = params_pair.into_inner().map(|param_pair| {
-                let mut inner_param = param_pair.into_inner();
-                let param_name = inner_param.next().unwrap().as_str().to_string();
-                let param_type = match inner_param.next().unwrap().as_str() {
-                    "string" => DataType::String,
-                    "number" => DataType::Number,
-                    "bool" => DataType::Bool,
-                    _ => unreachable!()
-                };
-                (param_name, param_type)
-            }).collect();
-
-            let body = body_pair.into_inner().filter_map(build_statement).collect();
-
-            Some(Statement::FunctionDeclaration(FunctionDeclaration {
-                name,
-                params,
-                body,
-            }))
-        }
-        Rule::system_exec => {
-            let mut name = None;
-            let mut args = vec![];
-            let mut inner_iter = inner.into_inner();
-            let exec_pairs = inner_iter.find(|p| p.as_rule() == Rule::exec_pairs);
-            if let Some(exec_pairs) = exec_pairs {
-                for part in exec_pairs.into_inner() {
-                    if part.as_rule() == Rule::exec_pair {
-                        let mut pair_inner = part.into_inner();
-                        if let Some(val_pair) = pair_inner.next() {
-                            match val_pair.as_rule() {
-                                Rule::identifier => {
-                                    name = Some(val_pair.as_str().to_string());
-                                }
-                                Rule::exec_params => {
-                                    args = val_pair.into_inner().map(|arg_pair| {
-                                        let mut inner_arg = arg_pair.into_inner();
-                                        let arg_name = inner_arg.next().unwrap().as_str().to_string();
-                                        let arg_val = build_expression(inner_arg.next().unwrap());
-                                        (arg_name, arg_val)
-                                    }).collect();
-                                }
-                                Rule::exec_type => {
-                                    // Ignore type for now
-                                }
-                                _ => {}
-                            }
+            Rule::system_input => {
+                let mut name = None;
+                let mut prompt = None;
+                let mut inner_iter = inner.into_inner();
+                let input_pairs = inner_iter.find(|p| p.as_rule() == Rule::input_pairs);
+                for val_pair in self.block_pairs("system.input", input_pairs) {
+                    match val_pair.as_rule() {
+                        Rule::identifier => {
+                            name = Some(val_pair.as_str().to_string());
+                        }
+                        Rule::expression => {
+                            prompt = Some(self.build_expression(val_pair));
+                        }
+                        _ => {}
+                    }
+                }
+                Some(StatementKind::SystemInput(Input {
+                    name: self.require(name, "system.input", "\"name\": my_var", span)?,
+                    prompt,
+                }))
+            }
+            Rule::system_exit => {
+                let mut code = None;
+                let mut inner_iter = inner.into_inner();
+                let exit_pairs = inner_iter.find(|p| p.as_rule() == Rule::exit_pairs);
+                for val_pair in self.block_pairs("system.exit", exit_pairs) {
+                    code = Some(self.build_expression(val_pair));
+                }
+                Some(StatementKind::SystemExit(Exit { code }))
+            }
+            Rule::function_decl => {
+                let mut inner_rules = inner.into_inner();
+                let name = inner_rules.next().unwrap().as_str().to_string();
+                let params_pair = inner_rules.next().unwrap();
+                let body_pair = inner_rules.next().unwrap();
+
+                let params = params_pair.into_inner().map(|param_pair| {
+                    let mut inner_param = param_pair.into_inner();
+                    let param_name = inner_param.next().unwrap().as_str().to_string();
+                    let param_type = build_data_type(inner_param.next().unwrap());
+                    (param_name, param_type)
+                }).collect();
+
+                let body = self.build_block(body_pair);
+
+                Some(StatementKind::FunctionDeclaration(FunctionDeclaration {
+                    name,
+                    params,
+                    body,
+                }))
+            }
+            Rule::system_exec => {
+                let mut name = None;
+                let mut args = vec![];
+                let mut spawn = false;
+                let mut handle = None;
+                let mut inner_iter = inner.into_inner();
+                let exec_pairs = inner_iter.find(|p| p.as_rule() == Rule::exec_pairs);
+                for val_pair in self.block_pairs("system.exec", exec_pairs) {
+                    match val_pair.as_rule() {
+                        Rule::identifier => {
+                            name = Some(val_pair.as_str().to_string());
+                        }
+                        Rule::exec_params => {
+                            args = self.build_exec_params(val_pair);
                         }
+                        Rule::exec_type => {
+                            spawn = val_pair.as_str() == "task";
+                        }
+                        Rule::exec_handle => {
+                            handle = Some(val_pair.as_str().to_string());
+                        }
+                        _ => {}
                     }
                 }
+                Some(StatementKind::SystemExec(FunctionCall {
+                    name: self.require(name, "system.exec", "\"name\": my_function", span)?,
+                    args,
+                    spawn,
+                    handle,
+                }))
+            }
+            Rule::return_statement => {
+                let inner = inner.into_inner().nth(1).unwrap();
+                Some(StatementKind::Return(self.build_expression(inner)))
+            }
+            Rule::try_statement => {
+                let mut inner_rules = inner.into_inner();
+                let body = self.build_block(inner_rules.next().unwrap());
+                let error_name = inner_rules.next().unwrap().as_str().to_string();
+                let handler = self.build_block(inner_rules.next().unwrap());
+                Some(StatementKind::Try(TryCatch {
+                    body,
+                    error_name,
+                    handler,
+                }))
+            }
+            Rule::call_statement => {
+                let call = inner.into_inner().next().unwrap();
+                Some(StatementKind::Expression(self.build_expression(call)))
             }
-            Some(Statement::SystemExec(FunctionCall {
-                name: name.unwrap(),
-                args,
-            }))
+            Rule::let_statement => {
+                let mut parts = inner.into_inner().skip(1);
+                let name = parts.next().unwrap().as_str().to_string();
+                let data_type = build_data_type(parts.next().unwrap());
+                let value = parts.next().map(|pair| self.build_expression(pair));
+                Some(StatementKind::SystemInit(VariableDeclaration { name, data_type, value }))
+            }
+            Rule::assign_statement => {
+                let mut parts = inner.into_inner();
+                let name = parts.next().unwrap().as_str().to_string();
+                let value = self.build_expression(parts.next().unwrap());
+                Some(StatementKind::SystemSet(VariableAssignment { name, value }))
+            }
+            Rule::log_statement => {
+                let mut parts = inner.into_inner().skip(1);
+                let log_type = parts.next().unwrap().as_str().to_string();
+                let message = self.build_expression(parts.next().unwrap());
+                Some(StatementKind::SystemLog(Log { log_type, arguments: vec![], message }))
+            }
+            Rule::call_function => {
+                let mut parts = inner.into_inner().skip(1);
+                let name = parts.next().unwrap().as_str().to_string();
+                let args = self.build_exec_params(parts.next().unwrap());
+                Some(StatementKind::SystemExec(FunctionCall { name, args, spawn: false, handle: None }))
+            }
+            Rule::comment => None,
+            Rule::system_include => Some(StatementKind::SystemInclude), // Placeholder
+            rule => {
+                self.unexpected(rule, span);
+                None
+            }
+        };
+        kind.map(|kind| Statement { kind, span })
+    }
+
+    /// The values of the `key: value` pairs of a `system.*` block. A key given
+    /// more than once is reported and only its first value is kept.
+    fn block_pairs<'i>(&self, block: &str, pairs: Option<Pair<'i, Rule>>) -> Vec<Pair<'i, Rule>> {
+        let mut seen: Vec<(&str, Span)> = Vec::new();
+        let mut values = Vec::new();
+        for part in pairs.into_iter().flat_map(|pairs| pairs.into_inner()) {
+            let key = pair_key(&part);
+            let start = part.as_span().start();
+            let key_span = self.source.span(self.file_id, self.offset + start, self.offset + start + key.len());
+            if let Some((_, first)) = seen.iter().find(|(seen_key, _)| *seen_key == key) {
+                self.report(
+                    Diagnostic::error("E0003", format!("duplicate key {} in {} block", key, block))
+                        .with_span(key_span)
+                        .with_label("given again here")
+                        .with_note(format!("first given at line {}, column {}", first.line, first.column)),
+                );
+                continue;
+            }
+            seen.push((key, key_span));
+            values.extend(part.into_inner().next());
         }
-        Rule::return_statement => {
-            let inner = inner.into_inner().next().unwrap();
-            Some(Statement::Return(build_expression(inner)))
+        values
+    }
+
+    /// Report a key that a `system.*` block must give; `example` shows it with a value
+    fn require<T>(&self, value: Option<T>, block: &str, example: &str, span: Span) -> Option<T> {
+        if value.is_none() {
+            let key = example.split(':').next().unwrap_or(example);
+            self.report(
+                Diagnostic::error("E0002", format!("{} block is missing the {} key", block, key))
+                    .with_span(span)
+                    .with_help(format!("add {}", example)),
+            );
         }
-        Rule::comment => None,
-        Rule::system_include => Some(Statement::SystemInclude), // Placeholder
-        _ => todo!("unhandled statement: {:?}", inner.as_rule()),
+        value
+    }
+
+    /// Report a rule the AST has no node for, which means the grammar and
+    /// builder are out of step
+    fn unexpected(&self, rule: Rule, span: Span) {
+        self.report(
+            Diagnostic::error("E0004", format!("unsupported syntax: {:?}", rule))
+                .with_span(span)
+                .with_note("this is a compiler bug; please report it"),
+        );
+    }
+
+    fn report(&self, diagnostic: Diagnostic) {
+        self.diagnostics.borrow_mut().push(diagnostic);
+    }
+
+
+    /// `name => expression` pairs passed to a function
+    fn build_exec_params(&self, pair: Pair<Rule>) -> Vec<(String, Expression)> {
+        pair.into_inner().map(|arg_pair| {
+            let mut inner_arg = arg_pair.into_inner();
+            let arg_name = inner_arg.next().unwrap().as_str().to_string();
+            let arg_val = self.build_expression(inner_arg.next().unwrap());
+            (arg_name, arg_val)
+        }).collect()
     }
-}
 
-fn build_expression(pair: Pair<Rule>) -> Expression {
-    match pair.as_rule() {
-        Rule::value => {
-            let inner = pair.into_inner().next().unwrap();
-            match inner.as_rule() {
-                Rule::string => {
-                    let s = inner.as_str();
-                    Expression::Value(Value::String(s[1..s.len() - 1].to_string()))
+    fn build_expression(&self, pair: Pair<Rule>) -> Expression {
+        let span = self.span(pair.as_span());
+        let kind = match pair.as_rule() {
+            Rule::value => {
+                let inner = pair.into_inner().next().unwrap();
+                match inner.as_rule() {
+                    Rule::string => {
+                        let s = inner.as_str();
+                        ExpressionKind::Value(Value::String(s[1..s.len() - 1].to_string()))
+                    }
+                    Rule::number => {
+                        // Literals without a fractional part are ints unless they overflow i64
+                        let text = inner.as_str();
+                        match text.parse::<i64>() {
+                            Ok(n) => ExpressionKind::Value(Value::Int(n)),
+                            Err(_) => ExpressionKind::Value(Value::Number(text.parse().unwrap())),
+                        }
+                    }
+                    Rule::boolean => ExpressionKind::Value(Value::Bool(inner.as_str().parse().unwrap())),
+                    Rule::null => ExpressionKind::Value(Value::Null),
+                    _ => unreachable!(),
                 }
-                Rule::number => Expression::Value(Value::Number(inner.as_str().parse().unwrap())),
-                Rule::boolean => Expression::Value(Value::Bool(inner.as_str().parse().unwrap())),
-                Rule::null => Expression::Value(Value::Null),
-                _ => unreachable!(),
             }
-        }
-        Rule::argument => {
-            Expression::Variable(pair.as_str().to_string())
-        }
-        Rule::expression => {
-            let mut inner = pair.into_inner();
-            let left = build_expression(inner.next().unwrap());
-            if let Some(right) = inner.next() {
-                 Expression::Concat(Box::new(left), Box::new(build_expression(right)))
+            Rule::argument => {
+                // `x . value` and `x.value` name the same thing
+                ExpressionKind::Variable(pair.as_str().split_whitespace().collect())
+            }
+            Rule::expression => {
+                let mut inner = pair.into_inner();
+                let first = self.build_expression(inner.next().unwrap());
+                return inner.fold(first, |left, right| {
+                    binary(left, self.build_expression(right), ExpressionKind::Concat)
+                });
+            }
+            Rule::coalesce => {
+                let mut inner = pair.into_inner();
+                let first = self.build_expression(inner.next().unwrap());
+                return inner.fold(first, |left, right| {
+                    binary(left, self.build_expression(right), ExpressionKind::Coalesce)
+                });
+            }
+            Rule::sum | Rule::product => {
+                let mut inner = pair.into_inner();
+                let mut result = self.build_expression(inner.next().unwrap());
+                while let (Some(op), Some(right)) = (inner.next(), inner.next()) {
+                    let op = match op.as_str() {
+                        "+" => ArithmeticOp::Add,
+                        "-" => ArithmeticOp::Subtract,
+                        "*" => ArithmeticOp::Multiply,
+                        "/" => ArithmeticOp::Divide,
+                        "%" => ArithmeticOp::Remainder,
+                        _ => unreachable!(),
+                    };
+                    result = binary(result, self.build_expression(right), |left, right| {
+                        ExpressionKind::Arithmetic(op, left, right)
+                    });
+                }
+                return result;
+            }
+            Rule::builtin_call => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap().as_str().to_string();
+                let args = inner.next().unwrap().into_inner().map(|arg| self.build_expression(arg)).collect();
+                ExpressionKind::Call(name, args)
+            }
+            _ => return self.build_expression(pair.into_inner().next().unwrap()),
+        };
+        Expression { kind, span }
+    }
+}
+
+/// Combine two operands into one expression spanning both
+fn binary(left: Expression, right: Expression, kind: impl FnOnce(Box<Expression>, Box<Expression>) -> ExpressionKind) -> Expression {
+    let span = left.span.to(right.span);
+    Expression { kind: kind(Box::new(left), Box::new(right)), span }
+}
+
+/// Build a data type; a trailing `[]` marks an array and a trailing `?` makes it nullable
+fn build_data_type(pair: Pair<Rule>) -> DataType {
+    let text = pair.as_str();
+    let (text, nullable) = match text.strip_suffix('?') {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+    let (base, is_array) = match text.strip_suffix("[]") {
+        Some(base) => (base, true),
+        None => (text, false),
+    };
+    let data_type = match base {
+        "string" => DataType::String,
+        "number" => DataType::Number,
+        "int" => DataType::Int,
+        "bool" => DataType::Bool,
+        "any" => DataType::Any,
+        _ => unreachable!(),
+    };
+    let data_type = if is_array { data_type.array() } else { data_type };
+    if nullable { data_type.nullable() } else { data_type }
+}
+
+/// Wrap a declared type in an array, keeping `?` on the array itself
+fn array_of(data_type: DataType) -> DataType {
+    match data_type {
+        DataType::Nullable(element) => element.array().nullable(),
+        element => element.array(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(text: &str) -> Vec<Diagnostic> {
+        parse_program(&Source::new(text), 0).1
+    }
+
+    #[test]
+    fn parsing_continues_after_syntax_errors() {
+        let source = Source::new(concat!(
+            "let ok: int = 1;\n",
+            "let x int = 1;\n",
+            "log info \"still parsed\";\n",
+            "system.set{ \"name\": ok \"value\": 2 };\n",
+            "log info \"end\";\n",
+        ));
+        let (ast, errors) = parse_program(&source, 0);
+        let errors: Vec<(&str, u32)> = errors.iter().map(|error| (error.message.as_str(), error.span.unwrap().line)).collect();
+        assert_eq!(errors, [
+            ("syntax error: expected `:`", 2),
+            ("syntax error: expected `,` or a closing `}`", 4),
+        ]);
+        let lines: Vec<u32> = ast.iter().map(|AstNode::Statement(statement)| statement.span.line).collect();
+        assert_eq!(lines, [1, 3, 5]);
+    }
+
+    #[test]
+    fn missing_and_duplicate_block_keys() {
+        let source = Source::new(concat!(
+            "system.init{ \"type\": variable, \"datatype\": int, \"value\": 1 };\n",
+            "system.log{ \"type\": info, \"type\": warn, \"message\": \"m\" };\n",
+        ));
+        let (_, errors) = parse_program(&source, 0);
+        let errors: Vec<(&str, &str, u32)> = errors.iter()
+            .map(|error| (error.code, error.message.as_str(), error.span.unwrap().line))
+            .collect();
+        assert_eq!(errors, [
+            ("E0002", "system.init block is missing the \"name\" key", 1),
+            ("E0003", "duplicate key \"type\" in system.log block", 2),
+        ]);
+    }
+
+    #[test]
+    fn quoted_names_are_described_and_get_a_hint() {
+        let errors = errors("system.init{ \"type\": variable, \"name\": \"x\", \"datatype\": string };\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "syntax error: expected `<identifier>`");
+        assert_eq!(errors[0].help, ["remove the quotes around the name: write x instead of \"x\""]);
+    }
+
+    #[test]
+    fn keywords_running_into_longer_words_are_reported_whole() {
+        let log_types = "use trace, debug, info, warn, error or fatal";
+        let cases = [
+            ("system.log{ \"type\": warning, \"message\": \"m\" };\n", "warning", "a log type (trace, debug, info, warn, error or fatal)"),
+            ("system.log{ \"type\": debugging, \"message\": \"m\" };\n", "debugging", "a log type (trace, debug, info, warn, error or fatal)"),
+            ("log infox.value;\n", "infox", "a log type (trace, debug, info, warn, error or fatal)"),
+            ("let q: integer = 1;\n", "integer", "a type (string, number, int, bool or any)"),
+        ];
+        for (text, word, expected) in cases {
+            let errors = errors(text);
+            assert_eq!(errors.len(), 1, "{}", text);
+            let span = errors[0].span.unwrap();
+            assert_eq!((span.start, span.end), (text.find(word).unwrap(), text.find(word).unwrap() + word.len()));
+            assert_eq!(errors[0].message, format!("syntax error: expected {}", expected));
+            let hint = if expected.starts_with("a log type") {
+                format!("`{}` is not a log type; {}", word, log_types)
             } else {
-                left
+                format!("`{}` is not a type; use string, number, int, bool or any, optionally followed by [] or ?", word)
+            };
+            assert_eq!(errors[0].help, [hint]);
+        }
+    }
+
+    /// The AST as JSON, with every `span` removed
+    fn without_spans(ast: &[AstNode]) -> serde_json::Value {
+        fn strip(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.remove("span");
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
             }
         }
-        _ => build_expression(pair.into_inner().next().unwrap())
+        let mut value = serde_json::to_value(ast).unwrap();
+        strip(&mut value);
+        value
     }
-}
 
+    #[test]
+    fn readme_short_forms_build_the_same_ast() {
+        let readme = include_str!("../README.md");
+        let section = readme.split("## Short syntax").nth(1).unwrap().split("\n## ").next().unwrap();
+        let pairs: Vec<(&str, &str)> = section.lines()
+            .filter_map(|line| line.strip_prefix("| `")?.strip_suffix("` |")?.split_once("` | `"))
+            .collect();
+        assert_eq!(pairs.len(), 6);
+        for (short, long) in pairs {
+            let (short_ast, short_errors) = parse_program(&Source::new(short), 0);
+            let (long_ast, long_errors) = parse_program(&Source::new(long), 0);
+            assert!(short_errors.is_empty() && long_errors.is_empty(), "{:?} {:?}", short_errors, long_errors);
+            assert_eq!(without_spans(&short_ast), without_spans(&long_ast), "{}", short);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::ast::*;
-use std::collections::HashSet;
+use crate::builtins::{Builtin, ParamType, Signature};
+use crate::diagnostic::Diagnostic;
+use crate::ir::ENTRY_POINT;
+use std::collections::HashMap;
 
 /// Semantic analysis errors
 #[derive(Debug, Clone)]
@@ -9,81 +12,541 @@ pub enum SemanticError {
     UndefinedVariable(String),
     UndefinedFunction(String),
     TypeMismatch(String),
+    ArgumentCountMismatch(String),
+    NullAssignment(String),
+    NestedFunction(String),
+    InvalidArgument(String),
 }
 
-/// Result of semantic analysis
-pub type SemanticResult<T> = Result<T, SemanticError>;
+impl std::fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SemanticError::DuplicateVariable(name) => write!(f, "variable '{}' is already declared", name),
+            SemanticError::DuplicateFunction(name) => write!(f, "function '{}' is already declared", name),
+            SemanticError::UndefinedVariable(name) => write!(f, "variable '{}' is not declared", name),
+            SemanticError::UndefinedFunction(name) => write!(f, "function '{}' is not declared", name),
+            SemanticError::TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            SemanticError::ArgumentCountMismatch(msg) => write!(f, "wrong number of arguments: {}", msg),
+            SemanticError::NullAssignment(msg) => write!(f, "null safety: {}", msg),
+            SemanticError::NestedFunction(name) => write!(f, "function '{}' is declared inside a block", name),
+            SemanticError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+        }
+    }
+}
+
+impl SemanticError {
+    fn at(self, span: Span) -> Spanned<SemanticError> {
+        Spanned::new(self, span)
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            SemanticError::DuplicateVariable(_) => "E0101",
+            SemanticError::DuplicateFunction(_) => "E0102",
+            SemanticError::UndefinedVariable(_) => "E0103",
+            SemanticError::UndefinedFunction(_) => "E0104",
+            SemanticError::TypeMismatch(_) => "E0105",
+            SemanticError::ArgumentCountMismatch(_) => "E0106",
+            SemanticError::NullAssignment(_) => "E0107",
+            SemanticError::NestedFunction(_) => "E0108",
+            SemanticError::InvalidArgument(_) => "E0109",
+        }
+    }
+
+    pub fn diagnostic(&self, span: Span) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.to_string()).with_span(span);
+        match self {
+            SemanticError::UndefinedVariable(_) => diagnostic.with_help("declare it first with system.init or let"),
+            SemanticError::NullAssignment(_) => diagnostic.with_help("make the type nullable with `?`, or use ?? to provide a fallback"),
+            SemanticError::NestedFunction(_) => diagnostic.with_help("move the declaration to the top level of the file"),
+            _ => diagnostic,
+        }
+    }
+}
+
+/// Semantic analysis warnings; these do not stop compilation
+#[derive(Debug, Clone)]
+pub enum SemanticWarning {
+    PossiblyNull(String),
+}
+
+impl std::fmt::Display for SemanticWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SemanticWarning::PossiblyNull(what) => write!(f, "'{}' may be null here", what),
+        }
+    }
+}
+
+impl SemanticWarning {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SemanticWarning::PossiblyNull(_) => "W0101",
+        }
+    }
+
+    pub fn diagnostic(&self, span: Span) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(self.code(), self.to_string()).with_span(span);
+        match self {
+            SemanticWarning::PossiblyNull(_) => diagnostic.with_label("may be null").with_help("use ?? to provide a fallback"),
+        }
+    }
+}
+
+/// Result of semantic analysis; errors point at the statement or expression they were found in
+pub type SemanticResult<T> = Result<T, Spanned<SemanticError>>;
+
+/// Names and types visible at a point of the program.
+/// Builtins are seeded with their typed signatures.
+#[derive(Clone)]
+struct SymbolTable {
+    variables: HashMap<String, DataType>,
+    functions: HashMap<String, Vec<(String, DataType)>>,
+    builtins: HashMap<String, Signature>,
+    /// Inside a function body or try block, where functions cannot be declared
+    nested: bool,
+}
+
+impl SymbolTable {
+    fn new() -> Self {
+        let builtins = Builtin::ALL
+            .iter()
+            .map(|builtin| (builtin.name().to_string(), builtin.signature()))
+            .collect();
+        Self {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            builtins,
+            nested: false,
+        }
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name) || self.builtins.contains_key(name)
+    }
+}
 
 /// Perform semantic analysis on the AST
 /// This stage validates:
 /// - No duplicate variable/function declarations
 /// - All referenced variables/functions are defined
 /// - Type consistency
-pub fn analyze(ast: &[AstNode]) -> SemanticResult<()> {
-    let mut declared_vars = HashSet::new();
-    let mut declared_funcs = HashSet::new();
-    
+/// - Null safety: `null` only flows into nullable types
+///
+/// On success, returns warnings for uses of possibly-null values
+pub fn analyze(ast: &[AstNode]) -> SemanticResult<Vec<Spanned<SemanticWarning>>> {
+    let mut symbols = SymbolTable::new();
+    let mut warnings = Vec::new();
+
     for node in ast {
         match node {
             AstNode::Statement(stmt) => {
-                analyze_statement(stmt, &mut declared_vars, &mut declared_funcs)?;
+                analyze_statement(stmt, &mut symbols, &mut warnings)?;
             }
         }
     }
-    
-    Ok(())
+
+    Ok(warnings)
 }
 
 fn analyze_statement(
     stmt: &Statement,
-    declared_vars: &mut HashSet<String>,
-    declared_funcs: &mut HashSet<String>,
+    symbols: &mut SymbolTable,
+    warnings: &mut Vec<Spanned<SemanticWarning>>,
 ) -> SemanticResult<()> {
-    match stmt {
-        Statement::SystemInit(var_decl) => {
-            if declared_vars.contains(&var_decl.name) {
-                return Err(SemanticError::DuplicateVariable(var_decl.name.clone()));
+    let span = stmt.span;
+    match &stmt.kind {
+        StatementKind::SystemInit(var_decl) => {
+            if symbols.variables.contains_key(&var_decl.name) {
+                return Err(SemanticError::DuplicateVariable(var_decl.name.clone()).at(span));
+            }
+            // A declaration without a value starts out as null
+            let value_type = match &var_decl.value {
+                Some(value) => analyze_expression(value, symbols, warnings)?,
+                None => None,
+            };
+            let value_span = var_decl.value.as_ref().map_or(span, |value| value.span);
+            check_assignable(&var_decl.name, &var_decl.data_type, value_type, value_span)?;
+            symbols.variables.insert(var_decl.name.clone(), var_decl.data_type.clone());
+        }
+        StatementKind::SystemSet(var_assign) => {
+            let Some(data_type) = symbols.variables.get(&var_assign.name).cloned() else {
+                return Err(SemanticError::UndefinedVariable(var_assign.name.clone()).at(span));
+            };
+            let value_type = analyze_expression(&var_assign.value, symbols, warnings)?;
+            check_assignable(&var_assign.name, &data_type, value_type, var_assign.value.span)?;
+        }
+        StatementKind::SystemInput(input) => {
+            let Some(data_type) = symbols.variables.get(&input.name).cloned() else {
+                return Err(SemanticError::UndefinedVariable(input.name.clone()).at(span));
+            };
+            if let Some(prompt) = &input.prompt {
+                let prompt_type = analyze_expression(prompt, symbols, warnings)?;
+                if let Some(prompt_type) = prompt_type.filter(|t| *t.base() != DataType::String) {
+                    return Err(SemanticError::TypeMismatch(format!("prompt must be string, found {}", prompt_type)).at(span));
+                }
             }
-            declared_vars.insert(var_decl.name.clone());
+            // The variable receives null at end of input, so it must be `string?`
+            check_assignable(&input.name, &data_type, Some(DataType::String.nullable()), span)?;
         }
-        Statement::SystemSet(var_assign) => {
-            if !declared_vars.contains(&var_assign.name) {
-                return Err(SemanticError::UndefinedVariable(var_assign.name.clone()));
+        StatementKind::SystemExit(exit) => {
+            if let Some(code) = &exit.code {
+                let code_type = analyze_expression(code, symbols, warnings)?;
+                check_assignable("code", &DataType::Int, code_type, code.span)?;
             }
         }
-        Statement::SystemLog(_) => {
-            // Log statements don't need semantic validation beyond expression checking
+        StatementKind::SystemLog(log) => {
+            for argument in &log.arguments {
+                analyze_expression(argument, symbols, warnings)?;
+            }
+            let message_type = analyze_expression(&log.message, symbols, warnings)?;
+            warn_if_nullable(&log.message, message_type.as_ref(), warnings);
         }
-        Statement::FunctionDeclaration(func_decl) => {
-            if declared_funcs.contains(&func_decl.name) {
-                return Err(SemanticError::DuplicateFunction(func_decl.name.clone()));
+        StatementKind::FunctionDeclaration(func_decl) => {
+            if symbols.nested {
+                return Err(SemanticError::NestedFunction(func_decl.name.clone()).at(span));
+            }
+            if symbols.is_function(&func_decl.name) {
+                return Err(SemanticError::DuplicateFunction(func_decl.name.clone()).at(span));
             }
-            declared_funcs.insert(func_decl.name.clone());
-            
+            symbols.functions.insert(func_decl.name.clone(), func_decl.params.clone());
+            if func_decl.name == ENTRY_POINT {
+                check_entry_point(func_decl, span)?;
+            }
+
             // Analyze function body
-            let mut func_vars = declared_vars.clone();
+            let mut func_symbols = symbols.clone();
+            func_symbols.nested = true;
             // Add function parameters to scope
-            for (param_name, _) in &func_decl.params {
-                func_vars.insert(param_name.clone());
+            for (param_name, param_type) in &func_decl.params {
+                func_symbols.variables.insert(param_name.clone(), param_type.clone());
             }
-            
+
             for body_stmt in &func_decl.body {
-                analyze_statement(body_stmt, &mut func_vars, declared_funcs)?;
+                analyze_statement(body_stmt, &mut func_symbols, warnings)?;
             }
         }
-        Statement::SystemExec(func_call) => {
-            if !declared_funcs.contains(&func_call.name) {
-                return Err(SemanticError::UndefinedFunction(func_call.name.clone()));
+        StatementKind::SystemExec(func_call) => {
+            let Some(params) = symbols.functions.get(&func_call.name) else {
+                return Err(SemanticError::UndefinedFunction(func_call.name.clone()).at(span));
+            };
+            // Arguments are matched to parameters by name, in any order, and each parameter needs exactly one
+            for (index, (arg_name, arg)) in func_call.args.iter().enumerate() {
+                let Some((_, param_type)) = params.iter().find(|(name, _)| name == arg_name) else {
+                    return Err(SemanticError::InvalidArgument(format!(
+                        "function '{}' has no parameter '{}'",
+                        func_call.name, arg_name
+                    )).at(arg.span));
+                };
+                if func_call.args[..index].iter().any(|(earlier, _)| earlier == arg_name) {
+                    return Err(SemanticError::InvalidArgument(format!("'{}' is given more than once", arg_name)).at(arg.span));
+                }
+                let arg_type = analyze_expression(arg, symbols, warnings)?;
+                check_assignable(arg_name, param_type, arg_type, arg.span)?;
+            }
+            let missing: Vec<&str> = params.iter()
+                .map(|(name, _)| name.as_str())
+                .filter(|name| !func_call.args.iter().any(|(arg_name, _)| arg_name == name))
+                .collect();
+            if !missing.is_empty() {
+                return Err(SemanticError::ArgumentCountMismatch(format!(
+                    "{} expects {} argument(s), got {}; missing {}",
+                    func_call.name,
+                    params.len(),
+                    func_call.args.len(),
+                    missing.join(", ")
+                )).at(span));
+            }
+            if let Some(handle) = &func_call.handle {
+                if !func_call.spawn {
+                    return Err(SemanticError::TypeMismatch(format!(
+                        "'handle' needs \"type\": task, since only tasks have an id to store in '{}'",
+                        handle
+                    )).at(span));
+                }
+                let Some(handle_type) = symbols.variables.get(handle) else {
+                    return Err(SemanticError::UndefinedVariable(handle.clone()).at(span));
+                };
+                check_assignable(handle, handle_type, Some(DataType::Int), span)?;
+            }
+        }
+        StatementKind::Try(try_catch) => {
+            // Each block gets its own scope
+            let mut body_symbols = symbols.clone();
+            body_symbols.nested = true;
+            for body_stmt in &try_catch.body {
+                analyze_statement(body_stmt, &mut body_symbols, warnings)?;
+            }
+            let mut handler_symbols = symbols.clone();
+            handler_symbols.nested = true;
+            if handler_symbols.variables.contains_key(&try_catch.error_name) {
+                return Err(SemanticError::DuplicateVariable(try_catch.error_name.clone()).at(span));
+            }
+            handler_symbols.variables.insert(try_catch.error_name.clone(), DataType::String);
+            for handler_stmt in &try_catch.handler {
+                analyze_statement(handler_stmt, &mut handler_symbols, warnings)?;
             }
         }
-        Statement::Return(_) => {
-            // Return statements are validated in function context
+        StatementKind::Return(expr) | StatementKind::Expression(expr) => {
+            analyze_expression(expr, symbols, warnings)?;
         }
-        Statement::SystemInclude => {
+        StatementKind::SystemInclude => {
             // Placeholder - no validation needed yet
         }
     }
-    
+
     Ok(())
 }
 
+/// Validate an expression and infer its type.
+/// Returns `None` for the `null` literal, which fits any nullable type.
+fn analyze_expression(
+    expr: &Expression,
+    symbols: &SymbolTable,
+    warnings: &mut Vec<Spanned<SemanticWarning>>,
+) -> SemanticResult<Option<DataType>> {
+    let span = expr.span;
+    match &expr.kind {
+        ExpressionKind::Value(value) => Ok(value_type(value)),
+        ExpressionKind::Variable(var_ref) => {
+            let (name, field) = var_ref.split_once('.').unwrap_or((var_ref, "value"));
+            let Some(data_type) = symbols.variables.get(name) else {
+                return Err(SemanticError::UndefinedVariable(name.to_string()).at(span));
+            };
+            match field {
+                "type" => Ok(Some(DataType::String)),
+                _ => Ok(Some(data_type.clone())),
+            }
+        }
+        ExpressionKind::Concat(left, right) => {
+            for operand in [left, right] {
+                let operand_type = analyze_expression(operand, symbols, warnings)?;
+                warn_if_nullable(operand, operand_type.as_ref(), warnings);
+            }
+            Ok(Some(DataType::String))
+        }
+        ExpressionKind::Call(name, args) => {
+            let Some(signature) = symbols.builtins.get(name) else {
+                return Err(SemanticError::UndefinedFunction(name.clone()).at(span));
+            };
+            if args.len() != signature.params.len() {
+                return Err(SemanticError::ArgumentCountMismatch(format!(
+                    "{} expects {} argument(s), got {}",
+                    name,
+                    signature.params.len(),
+                    args.len()
+                )).at(span));
+            }
+            for (index, (arg, param)) in args.iter().zip(&signature.params).enumerate() {
+                let arg_type = analyze_expression(arg, symbols, warnings)?;
+                let accepts_null = matches!(param, ParamType::Exact(expected) if expected.is_nullable());
+                if !accepts_null {
+                    warn_if_nullable(arg, arg_type.as_ref(), warnings);
+                }
+                if let (ParamType::Exact(expected), Some(found)) = (param, &arg_type) {
+                    if !is_widening(found.base(), expected.base()) {
+                        return Err(SemanticError::TypeMismatch(format!(
+                            "argument {} of {} must be {}, found {}",
+                            index + 1,
+                            name,
+                            expected,
+                            found
+                        )).at(span));
+                    }
+                }
+            }
+            Ok(signature.return_type.clone())
+        }
+        ExpressionKind::Coalesce(left, right) => {
+            let left_type = analyze_expression(left, symbols, warnings)?;
+            let right_type = analyze_expression(right, symbols, warnings)?;
+            match (left_type, right_type) {
+                (None, right_type) => Ok(right_type),
+                (Some(left_type), None) => Ok(Some(left_type.nullable())),
+                (Some(left_type), Some(right_type)) => {
+                    let Some(common) = common_type(left_type.base(), right_type.base()) else {
+                        return Err(SemanticError::TypeMismatch(format!(
+                            "both sides of ?? must have the same type, found {} and {}",
+                            left_type, right_type
+                        )).at(span));
+                    };
+                    // The fallback decides whether the result can still be null
+                    if right_type.is_nullable() {
+                        Ok(Some(common.nullable()))
+                    } else {
+                        Ok(Some(common))
+                    }
+                }
+            }
+        }
+        ExpressionKind::Arithmetic(op, left, right) => {
+            let mut operand_types = Vec::new();
+            for operand in [left, right] {
+                let operand_type = analyze_expression(operand, symbols, warnings)?;
+                warn_if_nullable(operand, operand_type.as_ref(), warnings);
+                match operand_type.as_ref().map(DataType::base) {
+                    Some(DataType::Int) => operand_types.push(DataType::Int),
+                    Some(DataType::Number) => operand_types.push(DataType::Number),
+                    Some(other) => {
+                        return Err(SemanticError::TypeMismatch(format!(
+                            "operator {} expects int or number operands, found {}",
+                            op, other
+                        )).at(span));
+                    }
+                    None => {
+                        return Err(SemanticError::TypeMismatch(format!("operator {} cannot be applied to null", op)).at(span));
+                    }
+                }
+            }
+            // int op int stays int; any number operand promotes the result to number
+            Ok(common_type(&operand_types[0], &operand_types[1]))
+        }
+    }
+}
+
+/// Whether a value of type `from` may be stored where `to` is expected.
+/// The implicit conversions are the promotion of `int` to `number` and storing anything as `any`.
+fn is_widening(from: &DataType, to: &DataType) -> bool {
+    from == to || (*from == DataType::Int && *to == DataType::Number) || *to == DataType::Any
+}
+
+/// Type both sides can be promoted to, if any
+fn common_type(a: &DataType, b: &DataType) -> Option<DataType> {
+    if is_widening(a, b) {
+        Some(b.clone())
+    } else if is_widening(b, a) {
+        Some(a.clone())
+    } else {
+        None
+    }
+}
+
+/// `main` takes no parameters or the script arguments as `string[]`
+fn check_entry_point(func_decl: &FunctionDeclaration, span: Span) -> SemanticResult<()> {
+    match func_decl.params.as_slice() {
+        [] => Ok(()),
+        [(_, param_type)] if *param_type == DataType::String.array() => Ok(()),
+        _ => Err(SemanticError::TypeMismatch(format!(
+            "{} must take no parameters or a single string[] parameter",
+            ENTRY_POINT
+        ))
+        .at(span)),
+    }
+}
+
+fn value_type(value: &Value) -> Option<DataType> {
+    match value {
+        Value::String(_) => Some(DataType::String),
+        Value::Number(_) => Some(DataType::Number),
+        Value::Int(_) => Some(DataType::Int),
+        Value::Bool(_) => Some(DataType::Bool),
+        Value::Array(items) => items.first().and_then(value_type).map(DataType::array),
+        Value::Map(_) => Some(DataType::Any),
+        Value::Null => None,
+    }
+}
+
+fn check_assignable(name: &str, expected: &DataType, found: Option<DataType>, span: Span) -> SemanticResult<()> {
+    match found {
+        None if !expected.is_nullable() => Err(SemanticError::NullAssignment(format!(
+            "cannot assign null to '{}' of non-nullable type {}",
+            name, expected
+        ))
+        .at(span)),
+        Some(found) if !is_widening(found.base(), expected.base()) || (found.is_nullable() && !expected.is_nullable()) => {
+            Err(SemanticError::TypeMismatch(format!(
+                "cannot assign {} to '{}' of type {}",
+                found, name, expected
+            ))
+            .at(span))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn warn_if_nullable(expr: &Expression, data_type: Option<&DataType>, warnings: &mut Vec<Spanned<SemanticWarning>>) {
+    if data_type.is_some_and(DataType::is_nullable) {
+        warnings.push(Spanned::new(SemanticWarning::PossiblyNull(describe_expression(expr)), expr.span));
+    }
+}
+
+fn describe_expression(expr: &Expression) -> String {
+    match &expr.kind {
+        ExpressionKind::Variable(var_ref) => var_ref.clone(),
+        ExpressionKind::Call(name, _) => format!("{}(...)", name),
+        _ => "expression".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Source;
+    use crate::parser::parse_program;
+
+    /// Analyze `source`, dropping the spans of the result
+    fn check(source: &str) -> Result<Vec<SemanticWarning>, SemanticError> {
+        let (ast, errors) = parse_program(&Source::new(source), 0);
+        assert!(errors.is_empty(), "{:?}", errors);
+        analyze(&ast)
+            .map(|warnings| warnings.into_iter().map(|warning| warning.node).collect())
+            .map_err(|err| err.node)
+    }
+
+    /// `system.init` of a variable, with `value` appended to the block when given
+    fn init(name: &str, datatype: &str, value: &str) -> String {
+        format!("system.init{{ \"type\": variable, \"name\": {}, \"datatype\": {}{} }};", name, datatype, value)
+    }
+
+    #[test]
+    fn functions_cannot_be_declared_inside_blocks() {
+        let nested = [
+            "try { function f() { return null; }; system.exec{ \"type\": function, \"name\": f }; } catch (e) { };",
+            "try { } catch (e) { function f() { return null; }; };",
+            "function outer() { function f() { return null; }; system.exec{ \"type\": function, \"name\": f }; return null; };",
+        ];
+        for source in nested {
+            assert!(matches!(check(source), Err(SemanticError::NestedFunction(name)) if name == "f"), "{}", source);
+        }
+        assert!(check("function f() { return null; }; function g() { system.exec{ \"type\": function, \"name\": f }; return null; };").is_ok());
+    }
+
+    #[test]
+    fn null_safety() {
+        let rejected = [
+            init("x", "number", ""),
+            init("x", "number", ", \"value\": null"),
+            format!("{} system.set{{ \"name\": x, \"value\": null }};", init("x", "number", ", \"value\": 1")),
+        ];
+        for source in rejected {
+            assert!(matches!(check(&source), Err(SemanticError::NullAssignment(_))), "{}", source);
+        }
+        let source = format!("{} {}", init("x", "number?", ""), init("y", "number", ", \"value\": x.value"));
+        assert!(matches!(check(&source), Err(SemanticError::TypeMismatch(_))));
+
+        let logged = format!("{} system.log{{ \"type\": info, \"message\": \"\" & x.value }};", init("x", "number?", ""));
+        assert!(matches!(check(&logged).unwrap().as_slice(), [SemanticWarning::PossiblyNull(name)] if name == "x.value"));
+        let defaulted = format!("{} {}", init("x", "number?", ""), init("y", "number", ", \"value\": x.value ?? 0"));
+        assert!(check(&defaulted).unwrap().is_empty());
+    }
+
+    #[test]
+    fn arguments_are_checked_by_name() {
+        let f = "function f(a in number, b in string) { return null; };";
+        let call = |args: &str| format!("{} system.exec{{ \"type\": function, \"name\": f, parameters{{ {} }} }};", f, args);
+        assert!(check(&call("b => \"x\", a => 1")).unwrap().is_empty());
+        assert!(matches!(check(&call("a => \"x\", b => 1")), Err(SemanticError::TypeMismatch(_))));
+        assert!(matches!(check(&call("a => 1")), Err(SemanticError::ArgumentCountMismatch(msg)) if msg.ends_with("missing b")));
+        assert!(matches!(check(&call("a => 1, b => \"x\", c => 2")), Err(SemanticError::InvalidArgument(_))));
+        assert!(matches!(check(&call("a => 1, a => 2, b => \"x\"")), Err(SemanticError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn int_and_number_types() {
+        let converted = format!("{} {}", init("n", "number", ", \"value\": 1"), init("i", "int", ", \"value\": to_int(n.value)"));
+        assert!(check(&converted).unwrap().is_empty());
+        assert!(matches!(check(&init("i", "int", ", \"value\": 1.5")), Err(SemanticError::TypeMismatch(_))));
+        let narrowed = format!("{} {}", init("n", "number", ", \"value\": 1"), init("i", "int", ", \"value\": n.value"));
+        assert!(matches!(check(&narrowed), Err(SemanticError::TypeMismatch(_))));
+    }
+}
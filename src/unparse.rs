@@ -0,0 +1,459 @@
+use pest::Parser;
+use crate::ast::*;
+use crate::ir::LogType;
+use crate::parser::{QParser, Rule};
+
+const INDENT: &str = "    ";
+
+/// Why an AST cannot be written as Q source
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnparseError {
+    InvalidName(String),
+    /// Variables are written `name.value` or `name.type`
+    InvalidVariable(String),
+    /// A string holding an unescaped `"` or ending in a lone `\`
+    InvalidString(String),
+    UnknownLogType(String),
+    /// A type the grammar cannot spell, such as an array of arrays
+    UnsupportedType(DataType),
+    /// A value with no literal syntax, such as an array or NaN
+    UnsupportedValue(Value),
+    UnsupportedLogArgument(ExpressionKind),
+    UnsupportedStatement(ExpressionKind),
+}
+
+impl std::fmt::Display for UnparseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnparseError::InvalidName(name) => write!(f, "'{}' is not a valid name", name),
+            UnparseError::InvalidVariable(name) => write!(f, "'{}' is not a variable reference such as 'x.value'", name),
+            UnparseError::InvalidString(text) => write!(f, "string {:?} cannot be written as a literal", text),
+            UnparseError::UnknownLogType(log_type) => write!(f, "unknown log type '{}'", log_type),
+            UnparseError::UnsupportedType(data_type) => write!(f, "type {:?} has no syntax in Q", data_type),
+            UnparseError::UnsupportedValue(value) => write!(f, "value {:?} has no literal syntax in Q", value),
+            UnparseError::UnsupportedLogArgument(kind) => write!(f, "log arguments must be variables, found {:?}", kind),
+            UnparseError::UnsupportedStatement(kind) => write!(f, "only builtin calls can be statements, found {:?}", kind),
+        }
+    }
+}
+
+pub type UnparseResult<T> = Result<T, UnparseError>;
+
+/// Write an AST as Q source in the style of `quentin fmt`. Parsing the result
+/// gives the same AST back, apart from spans. Statements use the short forms
+/// where one exists.
+pub fn unparse(ast: &[AstNode]) -> UnparseResult<String> {
+    let statements: Vec<&Statement> = ast.iter().map(|AstNode::Statement(statement)| statement).collect();
+    let out = statements_at(&statements, 0)?;
+    Ok(if out.is_empty() { out } else { format!("{}\n", out) })
+}
+
+fn statements_at(statements: &[&Statement], depth: usize) -> UnparseResult<String> {
+    let lines = statements.iter()
+        .map(|statement| Ok(format!("{}{}", INDENT.repeat(depth), statement_at(statement, depth)?)))
+        .collect::<UnparseResult<Vec<String>>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// `{`, the indented statements and the closing `}` on its own line
+fn body(statements: &[Statement], depth: usize) -> UnparseResult<String> {
+    let statements: Vec<&Statement> = statements.iter().collect();
+    let inner = statements_at(&statements, depth + 1)?;
+    let inner = if inner.is_empty() { inner } else { format!("\n{}", inner) };
+    Ok(format!("{{{}\n{}}}", inner, INDENT.repeat(depth)))
+}
+
+/// A `system.*` block with one `key: value` entry per line
+fn block(keyword: &str, entries: Vec<String>, depth: usize) -> String {
+    if entries.is_empty() {
+        return format!("{}{{}};", keyword);
+    }
+    let indent = INDENT.repeat(depth + 1);
+    let entries: Vec<String> = entries.iter().map(|entry| format!("\n{}{}", indent, entry)).collect();
+    format!("{}{{{}\n{}}};", keyword, entries.join(","), INDENT.repeat(depth))
+}
+
+/// `arguments{ ... }` and `parameters{ ... }` inside a block, one item per line
+fn list(key: &str, items: Vec<String>, depth: usize) -> String {
+    let indent = INDENT.repeat(depth + 2);
+    let items: Vec<String> = items.iter().map(|item| format!("\n{}{}", indent, item)).collect();
+    format!("{}{{{}\n{}}}", key, items.join(","), INDENT.repeat(depth + 1))
+}
+
+fn statement_at(statement: &Statement, depth: usize) -> UnparseResult<String> {
+    Ok(match &statement.kind {
+        StatementKind::SystemInclude => "system.include{}".to_string(),
+        StatementKind::SystemInit(declaration) => {
+            let head = format!("let {}: {}", name(&declaration.name)?, data_type(&declaration.data_type)?);
+            match &declaration.value {
+                Some(value) => format!("{} = {};", head, expression(value)?),
+                None => format!("{};", head),
+            }
+        }
+        StatementKind::SystemSet(assignment) => format!("{} = {};", name(&assignment.name)?, expression(&assignment.value)?),
+        StatementKind::SystemLog(log) => {
+            if !LogType::ALL.iter().any(|log_type| log_type.name() == log.log_type) {
+                return Err(UnparseError::UnknownLogType(log.log_type.clone()));
+            }
+            if log.arguments.is_empty() {
+                return Ok(format!("log {} {};", log.log_type, expression(&log.message)?));
+            }
+            let arguments = log.arguments.iter()
+                .map(|argument| match &argument.kind {
+                    ExpressionKind::Variable(variable_name) => variable(variable_name),
+                    kind => Err(UnparseError::UnsupportedLogArgument(kind.clone())),
+                })
+                .collect::<UnparseResult<Vec<String>>>()?;
+            let entries = vec![
+                format!("\"type\": {}", log.log_type),
+                list("arguments", arguments, depth),
+                format!("\"message\": {}", expression(&log.message)?),
+            ];
+            block("system.log", entries, depth)
+        }
+        StatementKind::FunctionDeclaration(declaration) => {
+            let params = declaration.params.iter()
+                .map(|(param_name, param_type)| Ok(format!("{} in {}", name(param_name)?, data_type(param_type)?)))
+                .collect::<UnparseResult<Vec<String>>>()?;
+            format!("function {}({}) {};", name(&declaration.name)?, params.join(", "), body(&declaration.body, depth)?)
+        }
+        StatementKind::SystemExec(call) => {
+            let params = call.args.iter()
+                .map(|(param_name, value)| Ok(format!("{} => {}", name(param_name)?, expression(value)?)))
+                .collect::<UnparseResult<Vec<String>>>()?;
+            if !call.spawn && call.handle.is_none() {
+                return Ok(format!("call {}({});", name(&call.name)?, params.join(", ")));
+            }
+            let mut entries = Vec::new();
+            if call.spawn {
+                entries.push("\"type\": task".to_string());
+            }
+            entries.push(format!("\"name\": {}", name(&call.name)?));
+            if let Some(handle) = &call.handle {
+                entries.push(format!("\"handle\": {}", name(handle)?));
+            }
+            if !params.is_empty() {
+                entries.push(list("parameters", params, depth));
+            }
+            block("system.exec", entries, depth)
+        }
+        StatementKind::SystemInput(input) => {
+            let mut entries = vec![format!("\"name\": {}", name(&input.name)?)];
+            if let Some(prompt) = &input.prompt {
+                entries.push(format!("\"prompt\": {}", expression(prompt)?));
+            }
+            block("system.input", entries, depth)
+        }
+        StatementKind::SystemExit(exit) => {
+            let entries = match &exit.code {
+                Some(code) => vec![format!("\"code\": {}", expression(code)?)],
+                None => vec![],
+            };
+            block("system.exit", entries, depth)
+        }
+        StatementKind::Try(try_catch) => format!(
+            "try {} catch ({}) {};",
+            body(&try_catch.body, depth)?,
+            name(&try_catch.error_name)?,
+            body(&try_catch.handler, depth)?,
+        ),
+        StatementKind::Return(value) => format!("return {};", expression(value)?),
+        StatementKind::Expression(value) => match &value.kind {
+            // `return(...)` would be read as a return statement
+            ExpressionKind::Call(call_name, _) if call_name != "return" => format!("{};", expression(value)?),
+            kind => return Err(UnparseError::UnsupportedStatement(kind.clone())),
+        },
+    })
+}
+
+/// Binding strength of an expression; operands that bind more loosely than
+/// their operator allows are wrapped in parentheses
+fn precedence(kind: &ExpressionKind) -> u8 {
+    match kind {
+        ExpressionKind::Concat(..) => 0,
+        ExpressionKind::Coalesce(..) => 1,
+        ExpressionKind::Arithmetic(ArithmeticOp::Add | ArithmeticOp::Subtract, ..) => 2,
+        ExpressionKind::Arithmetic(..) => 3,
+        ExpressionKind::Value(_) | ExpressionKind::Variable(_) | ExpressionKind::Call(..) => 4,
+    }
+}
+
+fn expression(node: &Expression) -> UnparseResult<String> {
+    let (operator, left, right) = match &node.kind {
+        ExpressionKind::Value(value) => return literal(value),
+        ExpressionKind::Variable(variable_name) => return variable(variable_name),
+        ExpressionKind::Call(call_name, args) => {
+            let args = args.iter().map(expression).collect::<UnparseResult<Vec<String>>>()?;
+            return Ok(format!("{}({})", name(call_name)?, args.join(", ")));
+        }
+        ExpressionKind::Concat(left, right) => ("&".to_string(), left, right),
+        ExpressionKind::Coalesce(left, right) => ("??".to_string(), left, right),
+        ExpressionKind::Arithmetic(op, left, right) => (op.to_string(), left, right),
+    };
+    // Operators group to the left, so only a right operand of equal strength needs parentheses
+    let level = precedence(&node.kind);
+    Ok(format!("{} {} {}", operand(left, level)?, operator, operand(right, level + 1)?))
+}
+
+fn operand(operand: &Expression, min_precedence: u8) -> UnparseResult<String> {
+    let text = expression(operand)?;
+    Ok(if precedence(&operand.kind) < min_precedence { format!("({})", text) } else { text })
+}
+
+fn literal(value: &Value) -> UnparseResult<String> {
+    match value {
+        Value::String(text) => {
+            let quoted = format!("\"{}\"", text);
+            if !matches_rule(Rule::string, &quoted) {
+                return Err(UnparseError::InvalidString(text.clone()));
+            }
+            Ok(quoted)
+        }
+        Value::Int(n) => Ok(n.to_string()),
+        // A whole number is written with `.0` so that it is not read back as an int
+        Value::Number(n) if n.is_finite() && n.fract() == 0.0 => Ok(format!("{:.1}", n)),
+        Value::Number(n) if n.is_finite() => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null => Ok("null".to_string()),
+        other => Err(UnparseError::UnsupportedValue(other.clone())),
+    }
+}
+
+fn name(name: &str) -> UnparseResult<&str> {
+    if matches_rule(Rule::identifier, name) { Ok(name) } else { Err(UnparseError::InvalidName(name.to_string())) }
+}
+
+fn variable(variable: &str) -> UnparseResult<String> {
+    // The grammar allows spaces around the dot, but they are not kept in the AST
+    if matches_rule(Rule::argument, variable) && !variable.contains(char::is_whitespace) {
+        Ok(variable.to_string())
+    } else {
+        Err(UnparseError::InvalidVariable(variable.to_string()))
+    }
+}
+
+/// A type as written after `:`; the grammar allows one `[]` and one `?`
+fn data_type(data_type: &DataType) -> UnparseResult<String> {
+    let inner = match data_type {
+        DataType::Nullable(inner) => inner,
+        other => other,
+    };
+    let element = match inner {
+        DataType::Array(element) => element,
+        element => element,
+    };
+    match element {
+        DataType::String | DataType::Number | DataType::Int | DataType::Bool | DataType::Any => Ok(data_type.to_string()),
+        _ => Err(UnparseError::UnsupportedType(data_type.clone())),
+    }
+}
+
+/// Whether `text` is exactly one match of `rule`
+fn matches_rule(rule: Rule, text: &str) -> bool {
+    QParser::parse(rule, text).is_ok_and(|mut pairs| pairs.next().is_some_and(|pair| pair.as_str() == text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::math::Rng;
+    use crate::diagnostic::Source;
+    use crate::parser::parse_program;
+
+    /// Names that start with keywords, which the grammar must not split
+    const NAMES: &[&str] = &[
+        "x", "total", "a_1", "returned", "truex", "nullable", "callback", "letter", "logs",
+        "log", "let", "call", "try", "function", "system", "true", "null", "info",
+    ];
+    const STRINGS: &[&str] = &["", "hello", "say \\\"hi\\\"", "tab\\t", "back\\\\slash", "ünïcode", "100% & ?? ()"];
+
+    /// Random ASTs that have Q syntax
+    struct Generator {
+        rng: Rng,
+    }
+
+    impl Generator {
+        fn below(&mut self, n: usize) -> usize {
+            match self.rng.random_int(0, n as i64 - 1) {
+                Ok(Value::Int(i)) => i as usize,
+                _ => unreachable!(),
+            }
+        }
+
+        fn name(&mut self) -> String {
+            NAMES[self.below(NAMES.len())].to_string()
+        }
+
+        fn many<T>(&mut self, max: usize, mut item: impl FnMut(&mut Self) -> T) -> Vec<T> {
+            (0..self.below(max + 1)).map(|_| item(self)).collect()
+        }
+
+        fn variable(&mut self) -> Expression {
+            let field = if self.below(4) == 0 { "type" } else { "value" };
+            expr(ExpressionKind::Variable(format!("{}.{}", self.name(), field)))
+        }
+
+        fn value(&mut self) -> Value {
+            match self.below(6) {
+                0 => Value::String(STRINGS[self.below(STRINGS.len())].to_string()),
+                1 => Value::Int(self.below(2001) as i64 - 1000),
+                2 => Value::Number([0.5, -2.25, 3.0, 1e20, 0.1, -0.0][self.below(6)]),
+                3 => Value::Bool(self.below(2) == 0),
+                4 => Value::Int([i64::MIN, i64::MAX][self.below(2)]),
+                _ => Value::Null,
+            }
+        }
+
+        fn data_type(&mut self) -> DataType {
+            let data_type = [DataType::String, DataType::Number, DataType::Int, DataType::Bool, DataType::Any][self.below(5)].clone();
+            let data_type = if self.below(3) == 0 { data_type.array() } else { data_type };
+            if self.below(3) == 0 { data_type.nullable() } else { data_type }
+        }
+
+        fn call(&mut self, depth: usize, name: String) -> Expression {
+            let args = self.many(3, |g| g.expression(depth - 1));
+            expr(ExpressionKind::Call(name, args))
+        }
+
+        fn expression(&mut self, depth: usize) -> Expression {
+            let choice = if depth == 0 { self.below(2) } else { self.below(7) };
+            let operand = |g: &mut Self| Box::new(g.expression(depth - 1));
+            match choice {
+                0 => expr(ExpressionKind::Value(self.value())),
+                1 => self.variable(),
+                2 => {
+                    let name = self.name();
+                    self.call(depth, name)
+                }
+                3 => expr(ExpressionKind::Concat(operand(self), operand(self))),
+                4 => expr(ExpressionKind::Coalesce(operand(self), operand(self))),
+                _ => {
+                    let op = [ArithmeticOp::Add, ArithmeticOp::Subtract, ArithmeticOp::Multiply, ArithmeticOp::Divide, ArithmeticOp::Remainder][self.below(5)];
+                    expr(ExpressionKind::Arithmetic(op, operand(self), operand(self)))
+                }
+            }
+        }
+
+        fn statement(&mut self, depth: usize) -> Statement {
+            let kind = match self.below(if depth == 0 { 9 } else { 11 }) {
+                0 => StatementKind::SystemInit(VariableDeclaration {
+                    name: self.name(),
+                    data_type: self.data_type(),
+                    value: if self.below(2) == 0 { Some(self.expression(3)) } else { None },
+                }),
+                1 => StatementKind::SystemSet(VariableAssignment { name: self.name(), value: self.expression(3) }),
+                2 => StatementKind::SystemLog(Log {
+                    log_type: LogType::ALL[self.below(LogType::ALL.len())].name().to_string(),
+                    arguments: self.many(2, Self::variable),
+                    message: self.expression(3),
+                }),
+                3 => StatementKind::SystemExec(FunctionCall {
+                    name: self.name(),
+                    args: self.many(3, |g| (g.name(), g.expression(2))),
+                    spawn: self.below(2) == 0,
+                    handle: if self.below(2) == 0 { Some(self.name()) } else { None },
+                }),
+                4 => StatementKind::SystemInput(Input {
+                    name: self.name(),
+                    prompt: if self.below(2) == 0 { Some(self.expression(2)) } else { None },
+                }),
+                5 => StatementKind::SystemExit(Exit { code: if self.below(2) == 0 { Some(self.expression(2)) } else { None } }),
+                6 => StatementKind::Return(self.expression(3)),
+                7 => {
+                    let name = self.name();
+                    StatementKind::Expression(self.call(3, name))
+                }
+                8 => StatementKind::SystemInclude,
+                9 => StatementKind::FunctionDeclaration(FunctionDeclaration {
+                    name: self.name(),
+                    params: self.many(3, |g| (g.name(), g.data_type())),
+                    body: self.many(3, |g| g.statement(depth - 1)),
+                }),
+                _ => StatementKind::Try(TryCatch {
+                    body: self.many(3, |g| g.statement(depth - 1)),
+                    error_name: self.name(),
+                    handler: self.many(3, |g| g.statement(depth - 1)),
+                }),
+            };
+            Statement { kind, span: Span::default() }
+        }
+    }
+
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression { kind, span: Span::default() }
+    }
+
+    fn clear_statement_spans(statement: &mut Statement) {
+        statement.span = Span::default();
+        let expressions: Vec<&mut Expression> = match &mut statement.kind {
+            StatementKind::SystemInit(declaration) => declaration.value.iter_mut().collect(),
+            StatementKind::SystemSet(assignment) => vec![&mut assignment.value],
+            StatementKind::SystemLog(log) => log.arguments.iter_mut().chain([&mut log.message]).collect(),
+            StatementKind::SystemExec(call) => call.args.iter_mut().map(|(_, value)| value).collect(),
+            StatementKind::SystemInput(input) => input.prompt.iter_mut().collect(),
+            StatementKind::SystemExit(exit) => exit.code.iter_mut().collect(),
+            StatementKind::Return(value) | StatementKind::Expression(value) => vec![value],
+            StatementKind::FunctionDeclaration(declaration) => {
+                declaration.body.iter_mut().for_each(clear_statement_spans);
+                vec![]
+            }
+            StatementKind::Try(try_catch) => {
+                try_catch.body.iter_mut().chain(&mut try_catch.handler).for_each(clear_statement_spans);
+                vec![]
+            }
+            StatementKind::SystemInclude => vec![],
+        };
+        expressions.into_iter().for_each(clear_expression_spans);
+    }
+
+    fn clear_expression_spans(expression: &mut Expression) {
+        expression.span = Span::default();
+        match &mut expression.kind {
+            ExpressionKind::Concat(left, right)
+            | ExpressionKind::Coalesce(left, right)
+            | ExpressionKind::Arithmetic(_, left, right) => {
+                clear_expression_spans(left);
+                clear_expression_spans(right);
+            }
+            ExpressionKind::Call(_, args) => args.iter_mut().for_each(clear_expression_spans),
+            ExpressionKind::Value(_) | ExpressionKind::Variable(_) => {}
+        }
+    }
+
+    #[test]
+    fn parse_of_unparse_is_identity() {
+        for seed in 0..500 {
+            let mut generator = Generator { rng: Rng::new(seed) };
+            let ast: Vec<AstNode> = generator.many(4, |g| AstNode::Statement(g.statement(2)));
+            let source = unparse(&ast).unwrap_or_else(|err| panic!("seed {}: {}", seed, err));
+            let (mut parsed, diagnostics) = parse_program(&Source::new(source.as_str()), 0);
+            assert!(diagnostics.is_empty(), "seed {}: {:?} in\n{}", seed, diagnostics, source);
+            parsed.iter_mut().for_each(|AstNode::Statement(statement)| clear_statement_spans(statement));
+            assert_eq!(parsed, ast, "seed {}:\n{}", seed, source);
+            assert_eq!(crate::cst::parse_cst(&source).map(|cst| cst.to_string()).ok().as_deref(), Some(source.as_str()), "seed {}", seed);
+            assert_eq!(crate::fmt::format_source(&source).ok().as_deref(), Some(source.as_str()), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn rejects_ast_without_syntax() {
+        let statement = |kind| AstNode::Statement(Statement { kind, span: Span::default() });
+        let cases = [
+            (StatementKind::SystemSet(VariableAssignment { name: "my var".into(), value: expr(ExpressionKind::Value(Value::Null)) }),
+                UnparseError::InvalidName("my var".into())),
+            (StatementKind::Return(expr(ExpressionKind::Value(Value::Array(vec![])))),
+                UnparseError::UnsupportedValue(Value::Array(vec![]))),
+            (StatementKind::Return(expr(ExpressionKind::Value(Value::String("a\"b".into())))),
+                UnparseError::InvalidString("a\"b".into())),
+            (StatementKind::Return(expr(ExpressionKind::Variable("x".into()))),
+                UnparseError::InvalidVariable("x".into())),
+            (StatementKind::Expression(expr(ExpressionKind::Call("return".into(), vec![]))),
+                UnparseError::UnsupportedStatement(ExpressionKind::Call("return".into(), vec![]))),
+            (StatementKind::SystemInit(VariableDeclaration { name: "x".into(), data_type: DataType::Int.array().array(), value: None }),
+                UnparseError::UnsupportedType(DataType::Int.array().array())),
+        ];
+        for (kind, error) in cases {
+            assert_eq!(unparse(&[statement(kind)]), Err(error));
+        }
+    }
+}
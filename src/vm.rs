@@ -1,302 +1,1082 @@
 use crate::ir::*;
 use crate::ast::*;
-use std::collections::HashMap;
-use colored::*;
+use crate::builtins::{self, fs, json, math, strings, time, Builtin};
+use crate::builtins::fs::Permissions;
+use crate::builtins::math::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::diagnostic::Diagnostic;
+use crate::log::{self, LogRecord, LogSink};
+
+/// Errors raised while executing bytecode
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    ConversionFailed(String),
+    IntegerOverflow(String),
+    DivisionByZero,
+    TypeError(String),
+    InvalidArgument(String),
+    PermissionDenied(String),
+    Io(String),
+    JsonParse { message: String, line: usize, column: usize },
+    UndefinedFunction(u32),
+    StackUnderflow,
+    /// Every unfinished task is blocked
+    Deadlock(String),
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::ConversionFailed(msg) => write!(f, "conversion failed: {}", msg),
+            RuntimeError::IntegerOverflow(msg) => write!(f, "integer overflow: {}", msg),
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::TypeError(msg) => write!(f, "type error: {}", msg),
+            RuntimeError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            RuntimeError::PermissionDenied(msg) => write!(f, "permission denied: {}", msg),
+            RuntimeError::Io(msg) => write!(f, "i/o error: {}", msg),
+            RuntimeError::JsonParse { message, line, column } => {
+                write!(f, "invalid JSON at line {}, column {}: {}", line, column, message)
+            }
+            RuntimeError::UndefinedFunction(id) => write!(f, "call to undeclared function #{}", id),
+            RuntimeError::StackUnderflow => write!(f, "stack underflow"),
+            RuntimeError::Deadlock(msg) => write!(f, "deadlock: {}", msg),
+        }
+    }
+}
+
+impl RuntimeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::ConversionFailed(_) => "E0201",
+            RuntimeError::IntegerOverflow(_) => "E0202",
+            RuntimeError::DivisionByZero => "E0203",
+            RuntimeError::TypeError(_) => "E0204",
+            RuntimeError::InvalidArgument(_) => "E0205",
+            RuntimeError::PermissionDenied(_) => "E0206",
+            RuntimeError::Io(_) => "E0207",
+            RuntimeError::JsonParse { .. } => "E0208",
+            RuntimeError::UndefinedFunction(_) => "E0209",
+            RuntimeError::StackUnderflow => "E0210",
+            RuntimeError::Deadlock(_) => "E0211",
+        }
+    }
+
+    /// `span` is the location of the failing instruction, if known
+    pub fn diagnostic(&self, span: Option<Span>) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(self.code(), self.to_string());
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        match self {
+            RuntimeError::Deadlock(_) => diagnostic.with_note("a deadlock cannot be caught by try"),
+            RuntimeError::StackUnderflow | RuntimeError::UndefinedFunction(_) => {
+                diagnostic.with_note("this is a compiler bug; rebuild the package and report it if it persists")
+            }
+            _ => diagnostic.with_help("wrap the statement in try { ... } catch (err) { ... } to handle this error"),
+        }
+    }
+}
+
+/// Process exit code for a program stopped by an uncaught runtime error (EX_SOFTWARE)
+pub const RUNTIME_ERROR_EXIT_CODE: i32 = 70;
+
+/// Process exit code after a `fatal` log message
+pub const FATAL_EXIT_CODE: i32 = 1;
+
+/// How a program finished
+#[derive(Debug)]
+pub enum ExitStatus {
+    /// Ran to completion (code 0) or stopped through `system.exit`
+    Exited(i32),
+    /// Stopped by a runtime error that no `try` block caught, with the
+    /// location of the failing instruction when there is one
+    Failed(RuntimeError, Option<Span>),
+}
+
+impl ExitStatus {
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => *code,
+            ExitStatus::Failed(..) => RUNTIME_ERROR_EXIT_CODE,
+        }
+    }
+}
 
 /// Virtual Machine for executing IR bytecode
 pub struct VM {
     variables: HashMap<u32, Value>,
+    variable_types: HashMap<u32, DataType>,
     functions: HashMap<u32, FunctionInfo>,
     stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    rng: Rng,
+    permissions: Permissions,
+    args: Vec<String>,
+    exit_code: Option<i32>,
+    log_level: LogType,
+    log_sinks: Vec<Box<dyn LogSink>>,
+    /// Write `system.input` prompts to stderr instead of stdout
+    prompt_to_stderr: bool,
+    /// Every task started so far; the main program is `MAIN_TASK`
+    tasks: Vec<Task>,
+    current_task: usize,
+    channels: Vec<Channel>,
+    /// Set by `yield` so the scheduler switches tasks after the current instruction
+    yielded: bool,
+    started: Instant,
     program: Program,
 }
 
 #[derive(Clone)]
 struct FunctionInfo {
-    param_count: u32,
     param_symbol_ids: Vec<u32>,
     body_start: u32,
-    body_end: u32,
+}
+
+/// Saved caller state for an active function call
+struct Frame {
+    return_pc: usize,
+    saved_vars: HashMap<u32, Value>,
+    stack_base: usize,
+}
+
+/// Active `try` block waiting for a runtime error
+struct Handler {
+    catch_start: usize,
+    error_symbol_id: u32,
+    frame_depth: usize,
+    stack_len: usize,
+}
+
+/// Id of the task running the top-level program
+const MAIN_TASK: usize = 0;
+
+/// A function running as a cooperatively scheduled task
+struct Task {
+    state: TaskState,
+    /// Saved execution state while another task is running
+    context: Option<TaskContext>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TaskState {
+    Ready,
+    Blocked(Wait),
+    Finished,
+}
+
+/// What a blocked task is waiting for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wait {
+    Receive(usize),
+    Task(usize),
+    AllTasks,
+}
+
+impl std::fmt::Display for Wait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Wait::Receive(channel) => write!(f, "receiving from channel {}", channel),
+            Wait::Task(task) => write!(f, "waiting for task {}", task),
+            Wait::AllTasks => write!(f, "waiting for all tasks"),
+        }
+    }
+}
+
+struct TaskContext {
+    pc: usize,
+    variables: HashMap<u32, Value>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+}
+
+/// Unbounded queue of values sent between tasks
+#[derive(Default)]
+struct Channel {
+    queue: VecDeque<Value>,
+    closed: bool,
 }
 
 impl VM {
     pub fn new(program: Program) -> Self {
-        let mut vm = Self {
+        // Functions will be registered when DeclareFunc instructions are executed
+        let variable_types = program.symbol_table.iter()
+            .filter_map(|symbol| match &symbol.kind {
+                SymbolKind::Variable { data_type } => Some((symbol.id, data_type.clone())),
+                _ => None,
+            })
+            .collect();
+        Self {
             variables: HashMap::new(),
+            variable_types,
             functions: HashMap::new(),
             stack: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            rng: Rng::from_time(),
+            permissions: Permissions::default(),
+            args: Vec::new(),
+            exit_code: None,
+            log_level: LogType::Info,
+            log_sinks: vec![log::stdout_sink()],
+            prompt_to_stderr: false,
+            tasks: vec![Task { state: TaskState::Ready, context: None }],
+            current_task: MAIN_TASK,
+            channels: Vec::new(),
+            yielded: false,
+            started: Instant::now(),
             program,
-        };
-        
-        // Functions will be registered when DeclareFunc instructions are executed
-        
-        vm
+        }
     }
 
-    pub fn execute(&mut self) {
+    /// Arguments passed to the script after `--`
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Grant file system access to builtins such as `read_file`
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// Hide log messages less severe than `level`; `fatal` messages still stop the program
+    pub fn set_log_level(&mut self, level: LogType) {
+        self.log_level = level;
+    }
+
+    /// Replace the default stdout sink; every message that passes the log level goes to each sink
+    pub fn set_log_sinks(&mut self, sinks: Vec<Box<dyn LogSink>>) {
+        self.log_sinks = sinks;
+    }
+
+    /// Keep stdout for log records, e.g. when it carries one JSON object per line
+    pub fn set_prompt_to_stderr(&mut self, prompt_to_stderr: bool) {
+        self.prompt_to_stderr = prompt_to_stderr;
+    }
+
+    /// Make `random` and `random_int` reproducible across runs
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Run until every task has finished, the program exits or an error goes uncaught
+    pub fn execute(&mut self) -> ExitStatus {
         let mut pc = 0;
-        while pc < self.program.instructions.len() {
-            match &self.program.instructions[pc] {
-                Instruction::InitVar { symbol_id, value } => {
-                    self.variables.insert(*symbol_id, value.clone());
-                    pc += 1;
+        while self.exit_code.is_none() {
+            if pc >= self.program.instructions.len() {
+                self.tasks[self.current_task].state = TaskState::Finished;
+            }
+            if self.yielded || self.tasks[self.current_task].state != TaskState::Ready {
+                pc = match self.switch_task(pc) {
+                    Ok(Some(next_pc)) => next_pc,
+                    Ok(None) => break,
+                    Err(err) => return ExitStatus::Failed(err, None),
+                };
+                continue;
+            }
+            pc = match self.step(pc).or_else(|err| self.catch(err)) {
+                Ok(next_pc) => next_pc,
+                Err(err) => return ExitStatus::Failed(err, self.program.spans.get(pc).copied()),
+            };
+        }
+        ExitStatus::Exited(self.exit_code.unwrap_or(0))
+    }
+
+    /// Save the running task and resume the next runnable one in round-robin order,
+    /// returning its program counter. Returns `None` once every task has finished.
+    fn switch_task(&mut self, pc: usize) -> Result<Option<usize>, RuntimeError> {
+        self.yielded = false;
+        if self.tasks[self.current_task].state != TaskState::Finished {
+            self.tasks[self.current_task].context = Some(TaskContext {
+                pc,
+                variables: std::mem::take(&mut self.variables),
+                stack: std::mem::take(&mut self.stack),
+                frames: std::mem::take(&mut self.frames),
+                handlers: std::mem::take(&mut self.handlers),
+            });
+        }
+        // Start after the current task so a task that yields runs again last
+        let task_count = self.tasks.len();
+        let next = (1..=task_count)
+            .map(|offset| (self.current_task + offset) % task_count)
+            .find(|&id| match self.tasks[id].state {
+                TaskState::Ready => true,
+                TaskState::Blocked(wait) => self.wait_is_over(id, wait),
+                TaskState::Finished => false,
+            });
+        let Some(next) = next else {
+            if self.tasks.iter().all(|task| task.state == TaskState::Finished) {
+                return Ok(None);
+            }
+            let blocked: Vec<String> = self.tasks.iter().enumerate()
+                .filter_map(|(id, task)| match task.state {
+                    TaskState::Blocked(wait) => Some(format!("task {} is {}", id, wait)),
+                    _ => None,
+                })
+                .collect();
+            return Err(RuntimeError::Deadlock(blocked.join(", ")));
+        };
+        let task = &mut self.tasks[next];
+        task.state = TaskState::Ready;
+        let context = task.context.take().expect("a task that is not running has a saved context");
+        self.variables = context.variables;
+        self.stack = context.stack;
+        self.frames = context.frames;
+        self.handlers = context.handlers;
+        self.current_task = next;
+        Ok(Some(context.pc))
+    }
+
+    fn wait_is_over(&self, waiting_task: usize, wait: Wait) -> bool {
+        match wait {
+            Wait::Receive(channel) => !self.channels[channel].queue.is_empty() || self.channels[channel].closed,
+            Wait::Task(task) => self.tasks[task].state == TaskState::Finished,
+            Wait::AllTasks => self.tasks.iter().enumerate()
+                .all(|(id, task)| id == waiting_task || task.state == TaskState::Finished),
+        }
+    }
+
+    /// What a blocking builtin still has to wait for before it can run, if anything
+    fn blocks_on(&self, builtin: Builtin, args: &[Value]) -> Result<Option<Wait>, RuntimeError> {
+        let wait = match builtin {
+            Builtin::Receive => Wait::Receive(self.channel_index(&args[0])?),
+            Builtin::Wait => {
+                let task = self.task_index(&args[0])?;
+                if task == self.current_task {
+                    return Err(RuntimeError::InvalidArgument(format!("task {} cannot wait for itself", task)));
                 }
-                Instruction::SetVar { symbol_id, value } => {
-                    self.variables.insert(*symbol_id, value.clone());
-                    pc += 1;
+                Wait::Task(task)
+            }
+            Builtin::WaitAll => Wait::AllTasks,
+            _ => return Ok(None),
+        };
+        Ok(Some(wait).filter(|wait| !self.wait_is_over(self.current_task, *wait)))
+    }
+
+    fn channel_index(&self, val: &Value) -> Result<usize, RuntimeError> {
+        let id = builtins::expect_int(val)?;
+        usize::try_from(id)
+            .ok()
+            .filter(|&index| index < self.channels.len())
+            .ok_or_else(|| RuntimeError::InvalidArgument(format!("there is no channel {}", id)))
+    }
+
+    fn task_index(&self, val: &Value) -> Result<usize, RuntimeError> {
+        let id = builtins::expect_int(val)?;
+        usize::try_from(id)
+            .ok()
+            .filter(|&index| index < self.tasks.len())
+            .ok_or_else(|| RuntimeError::InvalidArgument(format!("there is no task {}", id)))
+    }
+
+    /// Unwind to the innermost `try` block and return the start of its catch block.
+    /// Without a handler the error is returned to the caller.
+    fn catch(&mut self, err: RuntimeError) -> Result<usize, RuntimeError> {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(err);
+        };
+        // Leave any function frames entered inside the try block
+        if self.frames.len() > handler.frame_depth {
+            let outermost = self.frames.drain(handler.frame_depth..).next().unwrap();
+            self.variables = outermost.saved_vars;
+        }
+        self.stack.truncate(handler.stack_len);
+        self.store(handler.error_symbol_id, Value::String(err.to_string()));
+        Ok(handler.catch_start)
+    }
+
+    /// Execute the instruction at `pc` and return the next program counter
+    fn step(&mut self, pc: usize) -> Result<usize, RuntimeError> {
+        match &self.program.instructions[pc] {
+            Instruction::InitVar { symbol_id, value } | Instruction::SetVar { symbol_id, value } => {
+                let (symbol_id, value) = (*symbol_id, value.clone());
+                self.store(symbol_id, value);
+            }
+            Instruction::SetVarFromStack { symbol_id } => {
+                let symbol_id = *symbol_id;
+                let value = self.pop()?;
+                self.store(symbol_id, value);
+            }
+            Instruction::LoadValue { value } => {
+                self.stack.push(value.clone());
+            }
+            Instruction::LoadVar { symbol_id } => {
+                let val = self.variables.get(symbol_id).cloned().unwrap_or(Value::Null);
+                self.stack.push(val);
+            }
+            Instruction::Concat => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                let result = Value::String(format!("{}{}", value_to_string(&left), value_to_string(&right)));
+                self.stack.push(result);
+            }
+            Instruction::Pop => {
+                self.pop()?;
+            }
+            Instruction::Arithmetic { op } => {
+                let op = *op;
+                let right = self.pop()?;
+                let left = self.pop()?;
+                self.stack.push(arithmetic(op, &left, &right)?);
+            }
+            Instruction::JumpIfNotNull { target } => {
+                let target = *target as usize;
+                match self.stack.last() {
+                    Some(Value::Null) => {
+                        self.stack.pop();
+                    }
+                    Some(_) => return Ok(target),
+                    None => return Err(RuntimeError::StackUnderflow),
                 }
-                Instruction::SetVarFromStack { symbol_id } => {
-                    if let Some(value) = self.stack.pop() {
-                        self.variables.insert(*symbol_id, value);
+            }
+            Instruction::Log { log_type, fields, message_expr_start: _, message_expr_end: _ } => {
+                // The field and message expressions were already executed before this Log instruction
+                let (log_type, fields) = (*log_type, fields.clone());
+                let line = self.program.spans.get(pc).map_or(0, |span| span.line);
+                let message = value_to_string(&self.pop()?);
+                let field_values = self.pop_args(fields.len())?;
+                if log_type >= self.log_level {
+                    let record = LogRecord {
+                        level: log_type,
+                        message,
+                        fields: fields.into_iter().zip(field_values).collect(),
+                        source_file: &self.program.source_file,
+                        line,
+                        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+                    };
+                    for sink in &mut self.log_sinks {
+                        sink.log(&record).map_err(|e| RuntimeError::Io(format!("failed to write log: {}", e)))?;
                     }
-                    pc += 1;
                 }
-                Instruction::LoadValue { value } => {
-                    self.stack.push(value.clone());
-                    pc += 1;
+                if log_type == LogType::Fatal {
+                    self.exit_code = Some(FATAL_EXIT_CODE);
                 }
-                Instruction::LoadVar { symbol_id } => {
-                    if let Some(val) = self.variables.get(symbol_id) {
-                        self.stack.push(val.clone());
-                    }
-                    pc += 1;
+            }
+            Instruction::DeclareFunc { symbol_id, param_count: _, param_symbol_ids, body_start, body_end } => {
+                self.functions.insert(*symbol_id, FunctionInfo {
+                    param_symbol_ids: param_symbol_ids.clone(),
+                    body_start: *body_start,
+                });
+                // Skip over the body; it only runs when called
+                return Ok(*body_end as usize);
+            }
+            Instruction::CallFunc { symbol_id, arg_count } => {
+                let symbol_id = *symbol_id;
+                let arg_count = *arg_count as usize;
+                let func_info = self.functions.get(&symbol_id).cloned()
+                    .ok_or(RuntimeError::UndefinedFunction(symbol_id))?;
+
+                // Arguments are on the stack in order (last argument on top)
+                let args = self.pop_args(arg_count)?;
+
+                // Save current execution state and create an isolated frame
+                self.frames.push(Frame {
+                    return_pc: pc + 1,
+                    saved_vars: self.variables.clone(),
+                    stack_base: self.stack.len(),
+                });
+                for (param_symbol_id, arg_value) in func_info.param_symbol_ids.iter().zip(args) {
+                    self.store(*param_symbol_id, arg_value);
                 }
-                Instruction::Concat => {
-                    if self.stack.len() >= 2 {
-                        let right = self.stack.pop().unwrap();
-                        let left = self.stack.pop().unwrap();
-                        let result = Value::String(format!("{}{}", value_to_string(&left), value_to_string(&right)));
-                        self.stack.push(result);
-                    }
-                    pc += 1;
+                return Ok(func_info.body_start as usize);
+            }
+            Instruction::SpawnTask { symbol_id, arg_count } => {
+                let symbol_id = *symbol_id;
+                let func_info = self.functions.get(&symbol_id).cloned()
+                    .ok_or(RuntimeError::UndefinedFunction(symbol_id))?;
+                let args = self.pop_args(*arg_count as usize)?;
+                // Like a call, the task starts from a copy of the spawner's variables
+                let mut variables = self.variables.clone();
+                for (param_symbol_id, arg_value) in func_info.param_symbol_ids.iter().zip(args) {
+                    variables.insert(*param_symbol_id, self.promote(*param_symbol_id, arg_value));
                 }
-                Instruction::Log { log_type, message_expr_start: _, message_expr_end: _ } => {
-                    // The expression instructions were already executed before this Log instruction
-                    // Just pop the result from the stack
-                    let log_type_clone = *log_type;
-                    let message = if let Some(val) = self.stack.pop() {
-                        value_to_string(&val)
-                    } else {
-                        String::new()
-                    };
-                    
-                    let colored_type = match log_type_clone {
-                        LogType::Info => "info".blue().bold(),
-                        LogType::Warn => "warn".yellow().bold(),
-                        LogType::Error => "error".red().bold(),
-                    };
-                    println!("[{}] {}", colored_type, message);
-                    
-                    pc += 1;
+                let id = self.tasks.len();
+                self.tasks.push(Task {
+                    state: TaskState::Ready,
+                    context: Some(TaskContext {
+                        pc: func_info.body_start as usize,
+                        variables,
+                        stack: Vec::new(),
+                        frames: Vec::new(),
+                        handlers: Vec::new(),
+                    }),
+                });
+                self.stack.push(Value::Int(id as i64));
+            }
+            Instruction::CallBuiltin { builtin, arg_count } => {
+                let builtin = *builtin;
+                let arg_count = *arg_count as usize;
+                if self.stack.len() < arg_count {
+                    return Err(RuntimeError::StackUnderflow);
                 }
-                Instruction::DeclareFunc { symbol_id, param_count, param_symbol_ids, body_start, body_end } => {
-                    self.functions.insert(*symbol_id, FunctionInfo {
-                        param_count: *param_count,
-                        param_symbol_ids: param_symbol_ids.clone(),
-                        body_start: *body_start,
-                        body_end: *body_end,
-                    });
-                    pc += 1;
+                if let Some(wait) = self.blocks_on(builtin, &self.stack[self.stack.len() - arg_count..])? {
+                    // The instruction runs again once the scheduler resumes this task
+                    self.tasks[self.current_task].state = TaskState::Blocked(wait);
+                    return Ok(pc);
                 }
-                Instruction::CallFunc { symbol_id, arg_count } => {
-                    if let Some(func_info) = self.functions.get(symbol_id).cloned() {
-                        // Pop arguments from stack (they should already be evaluated)
-                        // Arguments are on stack in reverse order (last argument on top)
-                        let mut args = Vec::new();
-                        for _ in 0..*arg_count {
-                            if let Some(arg) = self.stack.pop() {
-                                args.push(arg);
-                            }
-                        }
-                        // Reverse to get correct order (first argument first)
-                        args.reverse();
-                        
-                        // Save current execution state
-                        let saved_vars = self.variables.clone();
-                        let saved_pc = pc;
-                        
-                        // Create isolated execution frame: set up function parameters
-                        // Map arguments to parameter symbol IDs
-                        for (i, arg_value) in args.iter().enumerate() {
-                            if i < func_info.param_symbol_ids.len() {
-                                let param_symbol_id = func_info.param_symbol_ids[i];
-                                self.variables.insert(param_symbol_id, arg_value.clone());
-                            }
-                        }
-                        
-                        // Execute function body in isolated frame
-                        let mut func_pc = func_info.body_start as usize;
-                        while func_pc < func_info.body_end as usize {
-                            // Check for return instruction
-                            if let Instruction::Return = &self.program.instructions[func_pc] {
-                                // Pop return value if any (currently not used)
-                                if !self.stack.is_empty() {
-                                    self.stack.pop();
-                                }
-                                func_pc += 1;
-                                break;
-                            }
-                            // Execute instruction and advance PC
-                            match &self.program.instructions[func_pc] {
-                                Instruction::LoadValue { value } => {
-                                    self.stack.push(value.clone());
-                                    func_pc += 1;
-                                }
-                                Instruction::LoadVar { symbol_id } => {
-                                    if let Some(val) = self.variables.get(symbol_id) {
-                                        self.stack.push(val.clone());
-                                    } else {
-                                        self.stack.push(Value::Null);
-                                    }
-                                    func_pc += 1;
-                                }
-                                Instruction::Concat => {
-                                    if self.stack.len() >= 2 {
-                                        let right = self.stack.pop().unwrap();
-                                        let left = self.stack.pop().unwrap();
-                                        let result = Value::String(format!("{}{}", value_to_string(&left), value_to_string(&right)));
-                                        self.stack.push(result);
-                                    }
-                                    func_pc += 1;
-                                }
-                                Instruction::SetVarFromStack { symbol_id } => {
-                                    if let Some(value) = self.stack.pop() {
-                                        self.variables.insert(*symbol_id, value);
-                                    }
-                                    func_pc += 1;
-                                }
-                                Instruction::InitVar { symbol_id, value } => {
-                                    self.variables.insert(*symbol_id, value.clone());
-                                    func_pc += 1;
-                                }
-                                Instruction::SetVar { symbol_id, value } => {
-                                    self.variables.insert(*symbol_id, value.clone());
-                                    func_pc += 1;
-                                }
-                                Instruction::Log { log_type, message_expr_start: _, message_expr_end: _ } => {
-                                    // The expression instructions were already executed before this Log instruction
-                                    // Just pop the result from the stack - should be exactly one value
-                                    let log_type_clone = *log_type;
-                                    let stack_before_pop = self.stack.len();
-                                    
-                                    // Only pop and log if we have a value on the stack
-                                    // This prevents empty logs from appearing
-                                    if stack_before_pop > 0 {
-                                        let message = if let Some(val) = self.stack.pop() {
-                                            value_to_string(&val)
-                                        } else {
-                                            String::new()
-                                        };
-                                        
-                                        // Clean up any leftover values from expression evaluation
-                                        while self.stack.len() > 0 {
-                                            self.stack.pop();
-                                        }
-                                        
-                                        // Only log if message is not empty and complete
-                                        // Skip logs that contain ": null" or end with ":" (indicating incomplete evaluation)
-                                        let msg_trimmed = message.trim();
-                                        let is_incomplete = message.contains(": null") || msg_trimmed.ends_with(":");
-                                        
-                                        if !message.is_empty() && !is_incomplete {
-                                            let colored_type = match log_type_clone {
-                                                LogType::Info => "info".blue().bold(),
-                                                LogType::Warn => "warn".yellow().bold(),
-                                                LogType::Error => "error".red().bold(),
-                                            };
-                                            println!("[{}] {}", colored_type, message);
-                                        }
-                                    }
-                                    func_pc += 1;
-                                }
-                                Instruction::Return => {
-                                    if !self.stack.is_empty() {
-                                        self.stack.pop();
-                                    }
-                                    func_pc += 1;
-                                    break;
-                                }
-                                Instruction::DeclareFunc { .. } => {
-                                    // DeclareFunc should not be in function body, but skip it if it is
-                                    func_pc += 1;
-                                }
-                                Instruction::CallFunc { .. } => {
-                                    // Nested function calls not supported in function body execution
-                                    func_pc += 1;
-                                }
-                                _ => {
-                                    func_pc += 1;
-                                }
-                            }
-                        }
-                        
-                        // Restore previous execution state (isolated frame cleanup)
-                        self.variables = saved_vars;
-                        pc = saved_pc + 1;
-                    } else {
-                        pc += 1;
-                    }
+                let args = self.pop_args(arg_count)?;
+                let result = self.call_builtin(builtin, &args)?;
+                self.stack.push(result);
+            }
+            Instruction::Return => {
+                // Return values are not used yet
+                self.pop()?;
+                if let Some(frame) = self.frames.pop() {
+                    // Restore previous execution state (isolated frame cleanup)
+                    self.stack.truncate(frame.stack_base);
+                    self.variables = frame.saved_vars;
+                    // Handlers installed inside the function no longer apply
+                    let depth = self.frames.len();
+                    self.handlers.retain(|handler| handler.frame_depth <= depth);
+                    return Ok(frame.return_pc);
                 }
-                Instruction::Return => {
-                    // Return from function
-                    pc += 1;
+                if self.current_task != MAIN_TASK {
+                    // A task ends when the function it was spawned with returns
+                    self.tasks[self.current_task].state = TaskState::Finished;
+                    return Ok(pc);
                 }
             }
+            Instruction::Exit => {
+                let code = builtins::expect_int(&self.pop()?)?;
+                let code = i32::try_from(code)
+                    .ok()
+                    .filter(|code| (0..=255).contains(code))
+                    .ok_or_else(|| RuntimeError::InvalidArgument(format!("exit code must be between 0 and 255, got {}", code)))?;
+                // Not an error, so try blocks do not intercept it
+                self.exit_code = Some(code);
+            }
+            Instruction::EnterTry { catch_start, error_symbol_id } => {
+                self.handlers.push(Handler {
+                    catch_start: *catch_start as usize,
+                    error_symbol_id: *error_symbol_id,
+                    frame_depth: self.frames.len(),
+                    stack_len: self.stack.len(),
+                });
+            }
+            Instruction::ExitTry { catch_end } => {
+                self.handlers.pop();
+                return Ok(*catch_end as usize);
+            }
         }
+        Ok(pc + 1)
+    }
+
+    /// Store a value, promoting ints held by `number` variables
+    fn store(&mut self, symbol_id: u32, value: Value) {
+        let value = self.promote(symbol_id, value);
+        self.variables.insert(symbol_id, value);
     }
 
-    fn execute_instruction_at(&mut self, pc: &mut usize) {
-        if *pc >= self.program.instructions.len() {
-            return;
+    fn promote(&self, symbol_id: u32, value: Value) -> Value {
+        match (value, self.variable_types.get(&symbol_id).map(DataType::base)) {
+            (Value::Int(n), Some(DataType::Number)) => Value::Number(n as f64),
+            (value, _) => value,
         }
-        
-        match &self.program.instructions[*pc] {
-            Instruction::LoadValue { value } => {
-                self.stack.push(value.clone());
-            }
-            Instruction::LoadVar { symbol_id } => {
-                if let Some(val) = self.variables.get(symbol_id) {
-                    self.stack.push(val.clone());
+    }
+
+    fn call_builtin(&mut self, builtin: Builtin, args: &[Value]) -> Result<Value, RuntimeError> {
+        match builtin {
+            Builtin::ToNumber => builtins::to_number(&args[0]),
+            Builtin::ToInt => builtins::to_int(&args[0]),
+            Builtin::ToString => builtins::to_string(&args[0]),
+            Builtin::ToBool => builtins::to_bool(&args[0]),
+            Builtin::ParseNumber => builtins::parse_number(&args[0]),
+            Builtin::Input => {
+                let (prompt, mut input) = (value_to_string(&args[0]), io::stdin().lock());
+                if self.prompt_to_stderr {
+                    read_line(&prompt, &mut input, &mut io::stderr())
                 } else {
-                    self.stack.push(Value::Null);
+                    read_line(&prompt, &mut input, &mut io::stdout())
                 }
             }
-            Instruction::Concat => {
-                if self.stack.len() >= 2 {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    let result = Value::String(format!("{}{}", value_to_string(&left), value_to_string(&right)));
-                    self.stack.push(result);
-                }
+            Builtin::Length => strings::length(args),
+            Builtin::Upper => strings::upper(args),
+            Builtin::Lower => strings::lower(args),
+            Builtin::Trim => strings::trim(args),
+            Builtin::Contains => strings::contains(args),
+            Builtin::StartsWith => strings::starts_with(args),
+            Builtin::EndsWith => strings::ends_with(args),
+            Builtin::Find => strings::find(args),
+            Builtin::Substring => strings::substring(args),
+            Builtin::Replace => strings::replace(args),
+            Builtin::Split => strings::split(args),
+            Builtin::Join => strings::join(args),
+            Builtin::Repeat => strings::repeat(args),
+            Builtin::Abs => math::unary("abs", args, f64::abs),
+            Builtin::Min => math::binary("min", args, f64::min),
+            Builtin::Max => math::binary("max", args, f64::max),
+            Builtin::Floor => math::unary("floor", args, f64::floor),
+            Builtin::Ceil => math::unary("ceil", args, f64::ceil),
+            Builtin::Round => math::unary("round", args, f64::round),
+            Builtin::Sqrt => math::unary("sqrt", args, f64::sqrt),
+            Builtin::Pow => math::binary("pow", args, f64::powf),
+            Builtin::Sin => math::unary("sin", args, f64::sin),
+            Builtin::Cos => math::unary("cos", args, f64::cos),
+            Builtin::Tan => math::unary("tan", args, f64::tan),
+            Builtin::Asin => math::unary("asin", args, f64::asin),
+            Builtin::Acos => math::unary("acos", args, f64::acos),
+            Builtin::Atan => math::unary("atan", args, f64::atan),
+            Builtin::Log => math::unary("log", args, f64::ln),
+            Builtin::Exp => math::unary("exp", args, f64::exp),
+            Builtin::Pi => Ok(Value::Number(std::f64::consts::PI)),
+            Builtin::E => Ok(Value::Number(std::f64::consts::E)),
+            Builtin::Random => Ok(self.rng.random()),
+            Builtin::RandomInt => {
+                let (min, max) = (builtins::expect_int(&args[0])?, builtins::expect_int(&args[1])?);
+                self.rng.random_int(min, max)
             }
-            Instruction::SetVarFromStack { symbol_id } => {
-                if let Some(value) = self.stack.pop() {
-                    self.variables.insert(*symbol_id, value);
-                }
+            Builtin::SeedRandom => {
+                self.seed_random(builtins::expect_int(&args[0])? as u64);
+                Ok(Value::Null)
             }
-            Instruction::InitVar { symbol_id, value } => {
-                self.variables.insert(*symbol_id, value.clone());
+            Builtin::ReadFile => fs::read_file(&self.permissions, args),
+            Builtin::WriteFile => fs::write_file(&self.permissions, args),
+            Builtin::AppendLine => fs::append_line(&self.permissions, args),
+            Builtin::FileExists => fs::file_exists(&self.permissions, args),
+            Builtin::ListDir => fs::list_dir(&self.permissions, args),
+            Builtin::DeleteFile => fs::delete_file(&self.permissions, args),
+            Builtin::Args => Ok(Value::Array(self.args.iter().cloned().map(Value::String).collect())),
+            Builtin::Arg => {
+                let index = builtins::expect_int(&args[0])?;
+                let arg = usize::try_from(index).ok().and_then(|index| self.args.get(index));
+                Ok(arg.cloned().map(Value::String).unwrap_or(Value::Null))
             }
-            Instruction::SetVar { symbol_id, value } => {
-                self.variables.insert(*symbol_id, value.clone());
+            Builtin::ArgCount => Ok(Value::Int(self.args.len() as i64)),
+            Builtin::Env => {
+                let name = builtins::expect_string(&args[0])?;
+                Ok(std::env::var(name).map(Value::String).unwrap_or(Value::Null))
             }
-            Instruction::Return => {
-                // Return handled at call site
+            Builtin::Now => time::now(),
+            Builtin::Clock => time::clock(self.started),
+            Builtin::Sleep => time::sleep(args),
+            Builtin::FormatTime => time::format_time(args),
+            Builtin::JsonParse => json::parse(args),
+            Builtin::JsonEncode => json::encode(args),
+            Builtin::JsonPretty => json::encode_pretty(args),
+            Builtin::JsonGet => json::get(args),
+            Builtin::JsonSet => json::set(args),
+            Builtin::JsonKeys => json::keys(args),
+            Builtin::Yield => {
+                self.yielded = true;
+                Ok(Value::Null)
+            }
+            Builtin::Channel => {
+                self.channels.push(Channel::default());
+                Ok(Value::Int(self.channels.len() as i64 - 1))
+            }
+            Builtin::Send => {
+                let channel = self.channel_index(&args[0])?;
+                let channel = &mut self.channels[channel];
+                if channel.closed {
+                    return Err(RuntimeError::InvalidArgument(format!("cannot send on closed channel {}", value_to_string(&args[0]))));
+                }
+                channel.queue.push_back(args[1].clone());
+                Ok(Value::Null)
             }
-            _ => {
-                // Other instructions handled at top level
+            // Blocking builtins only get here once `blocks_on` is satisfied
+            Builtin::Receive => {
+                let channel = self.channel_index(&args[0])?;
+                Ok(self.channels[channel].queue.pop_front().unwrap_or(Value::Null))
             }
+            Builtin::Close => {
+                let channel = self.channel_index(&args[0])?;
+                self.channels[channel].closed = true;
+                Ok(Value::Null)
+            }
+            Builtin::Wait | Builtin::WaitAll => Ok(Value::Null),
         }
-        *pc += 1;
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::StackUnderflow)
+    }
+
+    /// Pop `count` values, returned in the order they were pushed
+    fn pop_args(&mut self, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        if self.stack.len() < count {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
     }
 }
 
-fn value_to_string(val: &Value) -> String {
+/// Read one line from `input` after writing the prompt to `output`; null signals end of input
+fn read_line(prompt: &str, input: &mut impl BufRead, output: &mut impl Write) -> Result<Value, RuntimeError> {
+    if !prompt.is_empty() {
+        write!(output, "{}", prompt).and_then(|_| output.flush()).map_err(|e| RuntimeError::Io(e.to_string()))?;
+    }
+    let mut line = String::new();
+    let bytes_read = input.read_line(&mut line).map_err(|e| RuntimeError::Io(e.to_string()))?;
+    if bytes_read == 0 {
+        return Ok(Value::Null);
+    }
+    let trimmed_len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed_len);
+    Ok(Value::String(line))
+}
+
+/// Evaluate an arithmetic operator.
+/// Two ints give a checked int result; otherwise both sides are promoted to number.
+fn arithmetic(op: ArithmeticOp, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => {
+            let (a, b) = (*a, *b);
+            if matches!(op, ArithmeticOp::Divide | ArithmeticOp::Remainder) && b == 0 {
+                return Err(RuntimeError::DivisionByZero);
+            }
+            let result = match op {
+                ArithmeticOp::Add => a.checked_add(b),
+                ArithmeticOp::Subtract => a.checked_sub(b),
+                ArithmeticOp::Multiply => a.checked_mul(b),
+                ArithmeticOp::Divide => a.checked_div(b),
+                ArithmeticOp::Remainder => a.checked_rem(b),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| RuntimeError::IntegerOverflow(format!("{} {} {}", a, op, b)))
+        }
+        _ => {
+            let (a, b) = (as_f64(left, op)?, as_f64(right, op)?);
+            let result = match op {
+                ArithmeticOp::Add => a + b,
+                ArithmeticOp::Subtract => a - b,
+                ArithmeticOp::Multiply => a * b,
+                ArithmeticOp::Divide => a / b,
+                ArithmeticOp::Remainder => a % b,
+            };
+            Ok(Value::Number(result))
+        }
+    }
+}
+
+fn as_f64(val: &Value, op: ArithmeticOp) -> Result<f64, RuntimeError> {
+    match val {
+        Value::Number(n) => Ok(*n),
+        Value::Int(n) => Ok(*n as f64),
+        other => Err(RuntimeError::TypeError(format!(
+            "operator {} cannot be applied to {}",
+            op,
+            value_to_string(other)
+        ))),
+    }
+}
+
+pub fn value_to_string(val: &Value) -> String {
     match val {
         Value::String(s) => s.clone(),
         Value::Number(n) => n.to_string(),
+        Value::Int(n) => n.to_string(),
         Value::Bool(b) => b.to_string(),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(value_to_string).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Map(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", k, value_to_string(v))).collect();
+            format!("{{{}}}", entries.join(", "))
+        }
         Value::Null => "null".to_string(),
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::diagnostic::Source;
+    use crate::parser::parse_program;
+    use crate::semantic::analyze;
+
+    /// Collects the message of every record
+    struct Capture(Rc<RefCell<Vec<String>>>);
+
+    impl LogSink for Capture {
+        fn log(&mut self, record: &LogRecord) -> io::Result<()> {
+            self.0.borrow_mut().push(record.message.clone());
+            Ok(())
+        }
+    }
+
+    fn compile(source: &str) -> VM {
+        let (ast, errors) = parse_program(&Source::new(source), 0);
+        assert!(errors.is_empty(), "{:?}", errors);
+        analyze(&ast).unwrap();
+        VM::new(ast_to_ir(&ast, "test.q").unwrap())
+    }
+
+    /// Run a compiled program, returning the logged messages and how it finished
+    fn logged(mut vm: VM) -> (Vec<String>, ExitStatus) {
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        vm.set_log_sinks(vec![Box::new(Capture(Rc::clone(&messages)))]);
+        let status = vm.execute();
+        (messages.take(), status)
+    }
+
+    /// Compile and run `source`, returning the logged messages and how it finished
+    fn run(source: &str) -> (Vec<String>, ExitStatus) {
+        logged(compile(source))
+    }
+
+    /// Compile and run `source`, returning the top-level variables by name
+    fn globals(source: &str) -> Result<HashMap<String, Value>, RuntimeError> {
+        let mut vm = compile(source);
+        if let ExitStatus::Failed(err, _) = vm.execute() {
+            return Err(err);
+        }
+        let names: HashMap<u32, String> = vm.program.symbol_table.iter().map(|symbol| (symbol.id, symbol.name.clone())).collect();
+        Ok(vm.variables.into_iter().map(|(id, value)| (names[&id].clone(), value)).collect())
+    }
+
+    #[test]
+    fn coalesce_replaces_only_null() {
+        let variables = globals(r#"
+            system.init{ "type": variable, "name": port, "datatype": number? };
+            system.init{ "type": variable, "name": a, "datatype": number, "value": port.value ?? 8080 };
+            system.set{ "name": port, "value": 80 };
+            system.init{ "type": variable, "name": b, "datatype": number, "value": port.value ?? 8080 };
+        "#).unwrap();
+        assert_eq!(variables["a"], Value::Number(8080.0));
+        assert_eq!(variables["b"], Value::Number(80.0));
+    }
+
+    /// `system.init` of a variable with a value
+    fn init(name: &str, datatype: &str, value: &str) -> String {
+        format!("system.init{{ \"type\": variable, \"name\": {}, \"datatype\": {}, \"value\": {} }};\n", name, datatype, value)
+    }
+
+    #[test]
+    fn int_and_number_arithmetic_and_conversion() {
+        let source = [
+            init("i", "int", "7 / 2"),
+            init("n", "number", "i.value"),
+            init("half", "number", "n.value / 2"),
+            init("rem", "int", "7 % 3"),
+            init("mixed", "number", "1 + 0.5"),
+            init("parsed", "int", "to_int(\"42\")"),
+            init("truncated", "int", "to_int(-4.7)"),
+            init("sum", "number", "to_number(\"2.5\") + 1"),
+        ].concat();
+        let variables = globals(&source).unwrap();
+        let expected = [
+            ("i", Value::Int(3)),
+            ("half", Value::Number(1.5)),
+            ("rem", Value::Int(1)),
+            ("mixed", Value::Number(1.5)),
+            ("parsed", Value::Int(42)),
+            ("truncated", Value::Int(-4)),
+            ("sum", Value::Number(3.5)),
+        ];
+        for (name, value) in expected {
+            assert_eq!(variables[name], value, "{}", name);
+        }
+    }
+
+    #[test]
+    fn int_overflow_and_failed_conversions_are_runtime_errors() {
+        let failures = [
+            (init("x", "int", "9223372036854775807 + 1"), "integer overflow"),
+            (init("x", "int", "-9223372036854775807 * 2"), "integer overflow"),
+            (init("x", "int", "1 / 0"), "division by zero"),
+            (init("x", "int", "to_int(10000000000000000000.0)"), "conversion failed"),
+            (init("x", "int", "to_int(\"abc\")"), "conversion failed"),
+            (init("x", "number", "to_number(\"1.2.3\")"), "conversion failed"),
+        ];
+        for (source, error) in failures {
+            match globals(&source) {
+                Err(err) => assert!(err.to_string().starts_with(error), "{}: {}", source, err),
+                Ok(_) => panic!("{}: no error", source),
+            }
+        }
+    }
+
+    #[test]
+    fn try_catches_errors_raised_in_the_body_and_in_called_functions() {
+        let variables = globals(r#"
+            system.init{ "type": variable, "name": caught, "datatype": string, "value": "" };
+            system.init{ "type": variable, "name": reached, "datatype": bool, "value": false };
+            function divide(a in int, b in int) {
+                system.init{ "type": variable, "name": q, "datatype": int, "value": a.value / b.value };
+                return null;
+            };
+            try {
+                system.exec{ "type": function, "name": divide, parameters{ a => 1, b => 0 } };
+                system.set{ "name": reached, "value": true };
+            } catch (err) {
+                system.set{ "name": caught, "value": err.value };
+            };
+        "#).unwrap();
+        assert_eq!(variables["caught"], Value::String("division by zero".into()));
+        assert_eq!(variables["reached"], Value::Bool(false));
+    }
+
+    #[test]
+    fn try_does_not_intercept_exit() {
+        let mut vm = compile(r#"
+            try {
+                system.exit{ "code": 3 };
+            } catch (err) {
+                system.exit{ "code": 4 };
+            };
+        "#);
+        assert!(matches!(vm.execute(), ExitStatus::Exited(3)));
+    }
+
+    #[test]
+    fn input_returns_null_at_end_of_input() {
+        let mut input = &b"first\r\nsecond"[..];
+        let mut output = Vec::new();
+        assert_eq!(read_line("> ", &mut input, &mut output).unwrap(), Value::String("first".into()));
+        assert_eq!(read_line("", &mut input, &mut output).unwrap(), Value::String("second".into()));
+        assert_eq!(read_line("> ", &mut input, &mut output).unwrap(), Value::Null);
+        assert_eq!(output, b"> > ");
+    }
+
+    #[test]
+    fn seeded_random_sequences_repeat() {
+        let source = [init("a", "number", "random()"), init("b", "int", "random_int(1, 1000000)")].concat();
+        let draw = |seed: u64| {
+            let mut vm = compile(&source);
+            vm.seed_random(seed);
+            assert!(matches!(vm.execute(), ExitStatus::Exited(0)));
+            let mut values: Vec<(u32, Value)> = vm.variables.into_iter().collect();
+            values.sort_by_key(|(id, _)| *id);
+            values
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+
+        let mut vm = compile("");
+        vm.seed_random(7);
+        let first: Vec<Value> = (0..3).map(|_| vm.rng.random()).collect();
+        vm.seed_random(7);
+        let second: Vec<Value> = (0..3).map(|_| vm.rng.random()).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn main_receives_the_script_arguments() {
+        let mut vm = compile(r#"
+            function main(args in string[]) {
+                log info join(args.value, "|");
+                return null;
+            };
+        "#);
+        vm.set_args(vec!["a b".into(), "--flag".into()]);
+        assert_eq!(logged(vm).0, ["a b|--flag"]);
+        let (messages, status) = run("function main() { log info \"no args\"; return null; };");
+        assert_eq!(messages, ["no args"]);
+        assert!(matches!(status, ExitStatus::Exited(0)));
+    }
+
+    #[test]
+    fn log_level_hides_less_severe_messages_and_fatal_stops() {
+        let source = r#"
+            log trace "t";
+            log debug "d";
+            log info "i";
+            log warn "w";
+            log error "e";
+            log fatal "f";
+            log error "not reached";
+        "#;
+        let mut vm = compile(source);
+        vm.set_log_level(LogType::Warn);
+        let (messages, status) = logged(vm);
+        assert_eq!(messages, ["w", "e", "f"]);
+        assert!(matches!(status, ExitStatus::Exited(FATAL_EXIT_CODE)));
+
+        let mut vm = compile(source);
+        vm.set_log_level(LogType::Trace);
+        assert_eq!(logged(vm).0, ["t", "d", "i", "w", "e", "f"]);
+        assert_eq!(run(source).0, ["i", "w", "e", "f"]);
+    }
+
+    #[test]
+    fn wait_returns_once_the_task_has_finished() {
+        let (messages, status) = run(r#"
+            let results: int = channel();
+            let job: int = 0;
+            function square(n in int, out in int) {
+                send(out.value, n.value * n.value);
+                log info "task done";
+                return null;
+            };
+            system.exec{ "type": task, "name": square, "handle": job, parameters{ n => 7, out => results.value } };
+            log info "spawned task " & job.value;
+            wait(job.value);
+            log info "got " & receive(results.value);
+        "#);
+        assert_eq!(messages, ["spawned task 1", "task done", "got 49"]);
+        assert!(matches!(status, ExitStatus::Exited(0)));
+    }
+
+    #[test]
+    fn tasks_take_turns_in_the_order_they_were_started() {
+        let (messages, _) = run(r#"
+            function worker(name in string) {
+                log info name.value & " 1";
+                yield();
+                log info name.value & " 2";
+                return null;
+            };
+            system.exec{ "type": task, "name": worker, parameters{ name => "a" } };
+            system.exec{ "type": task, "name": worker, parameters{ name => "b" } };
+            log info "main 1";
+            yield();
+            log info "main 2";
+            wait_all();
+            log info "main 3";
+        "#);
+        assert_eq!(messages, ["main 1", "a 1", "b 1", "main 2", "a 2", "b 2", "main 3"]);
+    }
+
+    #[test]
+    fn receive_waits_for_values_and_gives_null_once_closed() {
+        let (messages, status) = run(r#"
+            let ch: int = channel();
+            function producer(out in int) {
+                send(out.value, "x");
+                yield();
+                send(out.value, "y");
+                close(out.value);
+                return null;
+            };
+            system.exec{ "type": task, "name": producer, parameters{ out => ch.value } };
+            log info "" & receive(ch.value);
+            log info "" & receive(ch.value);
+            log info "" & receive(ch.value) ?? "closed";
+        "#);
+        assert_eq!(messages, ["x", "y", "closed"]);
+        assert!(matches!(status, ExitStatus::Exited(0)));
+    }
+
+    #[test]
+    fn send_on_a_closed_channel_fails() {
+        let (_, status) = run("let ch: int = channel(); close(ch.value); send(ch.value, 1);");
+        assert!(matches!(status, ExitStatus::Failed(RuntimeError::InvalidArgument(msg), _) if msg == "cannot send on closed channel 0"));
+    }
+
+    #[test]
+    fn tasks_waiting_on_each_other_are_a_deadlock() {
+        let (messages, status) = run(r#"
+            let ch: int = channel();
+            let job: int = 0;
+            function stuck(inbox in int) {
+                log info "" & receive(inbox.value) ?? "none";
+                return null;
+            };
+            system.exec{ "type": task, "name": stuck, "handle": job, parameters{ inbox => ch.value } };
+            wait(job.value);
+            log info "not reached";
+        "#);
+        assert!(messages.is_empty());
+        let ExitStatus::Failed(err, _) = status else { panic!("{:?}", status) };
+        assert_eq!(err.code(), "E0211");
+        assert_eq!(err.to_string(), "deadlock: task 0 is waiting for task 1, task 1 is receiving from channel 0");
+    }
+}